@@ -10,8 +10,15 @@ mod inner {
 
         for &(src, dst) in &[
             ("src/config.in.rs", "config.rs"),
+            ("src/import.in.rs", "import.rs"),
+            ("src/command/chain.in.rs", "chain.rs"),
+            ("src/command/fuyu.in.rs", "fuyu.rs"),
+            ("src/command/quota.in.rs", "quota.rs"),
             ("src/command/tag.in.rs", "tag.rs"),
+            ("src/command/wolfram_alpha.in.rs", "wolfram_alpha.rs"),
             ("src/command/xkcd.in.rs", "xkcd.rs"),
+            ("src/command/xkcd_index.in.rs", "xkcd_index.rs"),
+            ("src/command/xkcd_search.in.rs", "xkcd_search.rs"),
         ] {
             let src = Path::new(src);
             let dst = Path::new(&out_dir).join(dst);
@@ -0,0 +1,1124 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides an offline calculator, which evaluates arithmetic expressions and
+//! converts between units without calling any external API.
+//!
+//! Expressions may use the operators `+ - * / % ^ !`, functions such as `sin`,
+//! `log` and `sqrt`, the constants `pi`, `e`, `tau`, `phi` and `c`, and
+//! numbers with units, e.g. `5 km / 20 min`. A result may be converted to other
+//! units with `in` or `to`, e.g. `5 km in miles` or `100 F to C`.
+//!
+//! Variables are assigned with `x = 2` and are remembered per user. The last
+//! result is available as `ans`.
+
+use serenity::model::UserId;
+use std::collections::HashMap;
+use std::f64::consts;
+use std::sync::Mutex;
+use util::check_msg;
+
+lazy_static! {
+    static ref VARIABLES: Mutex<HashMap<UserId, HashMap<String, Quantity>>> =
+        Mutex::new(HashMap::new());
+}
+
+// The number of base dimensions: length, mass, time and temperature.
+const DIMENSIONS: usize = 4;
+// The SI base unit of each dimension.
+const BASE_UNITS: [&'static str; DIMENSIONS] = ["m", "kg", "s", "K"];
+
+type Dimension = [i32; DIMENSIONS];
+
+const DIMENSIONLESS: Dimension = [0, 0, 0, 0];
+const LENGTH: Dimension = [1, 0, 0, 0];
+const AREA: Dimension = [2, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0];
+const SPEED: Dimension = [1, 0, -1, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 1];
+
+static DIMENSION_NAMES: &'static [(Dimension, &'static str)] = &[
+    (DIMENSIONLESS, "a plain number"),
+    (LENGTH, "length"),
+    (AREA, "area"),
+    (VOLUME, "volume"),
+    (MASS, "mass"),
+    (TIME, "time"),
+    (SPEED, "speed"),
+    (TEMPERATURE, "temperature"),
+];
+
+// A unit of measurement, defined in terms of SI base units.
+struct Unit {
+    names: &'static [&'static str],
+    // The value of one of this unit in SI base units.
+    factor: f64,
+    // The value of zero of this unit in SI base units, which is only non-zero
+    // for temperature scales.
+    offset: f64,
+    dimension: Dimension,
+}
+
+macro_rules! unit {
+    ($names:expr, $factor:expr, $dimension:expr) => {
+        Unit { names: $names, factor: $factor, offset: 0.0, dimension: $dimension }
+    };
+}
+
+static UNITS: &'static [Unit] = &[
+    unit!(&["m", "meter", "meters", "metre", "metres"], 1.0, LENGTH),
+    unit!(&["km", "kilometer", "kilometers", "kilometre", "kilometres"], 1000.0, LENGTH),
+    unit!(&["cm", "centimeter", "centimeters", "centimetre", "centimetres"], 0.01, LENGTH),
+    unit!(&["mm", "millimeter", "millimeters", "millimetre", "millimetres"], 0.001, LENGTH),
+    unit!(&["um", "micrometer", "micrometers", "micron", "microns"], 1e-6, LENGTH),
+    unit!(&["nm", "nanometer", "nanometers"], 1e-9, LENGTH),
+    unit!(&["mi", "mile", "miles"], 1609.344, LENGTH),
+    unit!(&["yd", "yard", "yards"], 0.9144, LENGTH),
+    unit!(&["ft", "foot", "feet"], 0.3048, LENGTH),
+    unit!(&["inch", "inches"], 0.0254, LENGTH),
+    unit!(&["nmi"], 1852.0, LENGTH),
+    unit!(&["au"], 1.495978707e11, LENGTH),
+    unit!(&["ly", "lightyear", "lightyears"], 9.4607304725808e15, LENGTH),
+    unit!(&["ha", "hectare", "hectares"], 1e4, AREA),
+    unit!(&["acre", "acres"], 4046.8564224, AREA),
+    unit!(&["l", "L", "liter", "liters", "litre", "litres"], 1e-3, VOLUME),
+    unit!(&["ml", "mL", "milliliter", "milliliters", "millilitre", "millilitres"],
+          1e-6,
+          VOLUME),
+    unit!(&["gal", "gallon", "gallons"], 3.785411784e-3, VOLUME),
+    unit!(&["qt", "quart", "quarts"], 9.46352946e-4, VOLUME),
+    unit!(&["pt", "pint", "pints"], 4.73176473e-4, VOLUME),
+    unit!(&["cup", "cups"], 2.365882365e-4, VOLUME),
+    unit!(&["floz"], 2.95735295625e-5, VOLUME),
+    unit!(&["kg", "kilogram", "kilograms"], 1.0, MASS),
+    unit!(&["g", "gram", "grams"], 1e-3, MASS),
+    unit!(&["mg", "milligram", "milligrams"], 1e-6, MASS),
+    unit!(&["t", "tonne", "tonnes"], 1000.0, MASS),
+    unit!(&["lb", "lbs", "pound", "pounds"], 0.45359237, MASS),
+    unit!(&["oz", "ounce", "ounces"], 0.028349523125, MASS),
+    unit!(&["st", "stone", "stones"], 6.35029318, MASS),
+    unit!(&["s", "sec", "secs", "second", "seconds"], 1.0, TIME),
+    unit!(&["ms", "millisecond", "milliseconds"], 1e-3, TIME),
+    unit!(&["min", "mins", "minute", "minutes"], 60.0, TIME),
+    unit!(&["h", "hr", "hrs", "hour", "hours"], 3600.0, TIME),
+    unit!(&["day", "days"], 86400.0, TIME),
+    unit!(&["week", "weeks"], 604800.0, TIME),
+    // A Julian year.
+    unit!(&["yr", "year", "years"], 31557600.0, TIME),
+    unit!(&["mph"], 0.44704, SPEED),
+    unit!(&["kph"], 0.2777777777777778, SPEED),
+    unit!(&["kn", "knot", "knots"], 0.5144444444444445, SPEED),
+    unit!(&["K", "kelvin"], 1.0, TEMPERATURE),
+    Unit {
+        names: &["C", "degC", "celsius"],
+        factor: 1.0,
+        offset: 273.15,
+        dimension: TEMPERATURE,
+    },
+    Unit {
+        names: &["F", "degF", "fahrenheit"],
+        factor: 0.5555555555555556,
+        offset: 255.3722222222222,
+        dimension: TEMPERATURE,
+    },
+];
+
+static CONSTANTS: &'static [(&'static str, f64, Dimension)] = &[
+    ("pi", consts::PI, DIMENSIONLESS),
+    ("e", consts::E, DIMENSIONLESS),
+    ("tau", 6.283185307179586, DIMENSIONLESS),
+    ("phi", 1.618033988749895, DIMENSIONLESS),
+    // The speed of light.
+    ("c", 299792458.0, SPEED),
+];
+
+static FUNCTIONS: &'static [&'static str] = &[
+    "sin", "cos", "tan", "asin", "acos", "atan", "sinh", "cosh", "tanh", "sqrt", "cbrt", "abs",
+    "exp", "ln", "log", "log2", "floor", "ceil", "round", "min", "max",
+];
+
+// Words which separate an expression from the units to convert it to.
+static CONVERSION_KEYWORDS: &'static [&'static str] = &["in", "to", "as"];
+
+// A value together with its dimension, e.g. length or speed. Values are
+// always stored in SI base units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Quantity {
+    value: f64,
+    dimension: Dimension,
+}
+
+impl Quantity {
+    fn number(value: f64) -> Self {
+        Quantity {
+            value: value,
+            dimension: DIMENSIONLESS,
+        }
+    }
+
+    fn is_dimensionless(&self) -> bool {
+        self.dimension == DIMENSIONLESS
+    }
+
+    fn add(self, other: Quantity) -> Result<Quantity, String> {
+        if self.dimension != other.dimension {
+            return Err(format!("Cannot add or subtract {} and {}.",
+                               describe_dimension(self.dimension),
+                               describe_dimension(other.dimension)));
+        }
+
+        Ok(Quantity {
+            value: self.value + other.value,
+            dimension: self.dimension,
+        })
+    }
+
+    fn negate(self) -> Quantity {
+        Quantity {
+            value: -self.value,
+            dimension: self.dimension,
+        }
+    }
+
+    fn multiply(self, other: Quantity) -> Result<Quantity, String> {
+        let mut dimension = self.dimension;
+        for (d, o) in dimension.iter_mut().zip(other.dimension.iter()) {
+            *d = d.checked_add(*o).ok_or_else(units_too_large)?;
+        }
+
+        Ok(Quantity {
+            value: self.value * other.value,
+            dimension: dimension,
+        })
+    }
+
+    fn divide(self, other: Quantity) -> Result<Quantity, String> {
+        if other.value == 0.0 {
+            return Err("Division by zero.".to_owned());
+        }
+
+        let mut dimension = self.dimension;
+        for (d, o) in dimension.iter_mut().zip(other.dimension.iter()) {
+            *d = d.checked_sub(*o).ok_or_else(units_too_large)?;
+        }
+
+        Ok(Quantity {
+            value: self.value / other.value,
+            dimension: dimension,
+        })
+    }
+
+    fn remainder(self, other: Quantity) -> Result<Quantity, String> {
+        if self.dimension != other.dimension {
+            return Err(format!("Cannot take the remainder of {} and {}.",
+                               describe_dimension(self.dimension),
+                               describe_dimension(other.dimension)));
+        }
+        if other.value == 0.0 {
+            return Err("Division by zero.".to_owned());
+        }
+
+        Ok(Quantity {
+            value: self.value % other.value,
+            dimension: self.dimension,
+        })
+    }
+
+    fn pow(self, exponent: Quantity) -> Result<Quantity, String> {
+        if !exponent.is_dimensionless() {
+            return Err("Exponents cannot have units.".to_owned());
+        }
+        // Quantities with units may only be raised to integer powers, so that
+        // the result has a sensible dimension.
+        if !self.is_dimensionless() && exponent.value.fract() != 0.0 {
+            return Err("Quantities with units can only be raised to integer powers.".to_owned());
+        }
+
+        let mut dimension = self.dimension;
+        if !self.is_dimensionless() {
+            // Larger exponents would not fit in the dimension, and casting them
+            // would silently change their value.
+            if exponent.value.abs() > i32::max_value() as f64 {
+                return Err(units_too_large());
+            }
+            for d in &mut dimension {
+                *d = d.checked_mul(exponent.value as i32).ok_or_else(units_too_large)?;
+            }
+        }
+
+        Ok(Quantity {
+            value: self.value.powf(exponent.value),
+            dimension: dimension,
+        })
+    }
+
+    fn factorial(self) -> Result<Quantity, String> {
+        if !self.is_dimensionless() || self.value < 0.0 || self.value.fract() != 0.0 {
+            return Err("Factorials are only defined for non-negative integers.".to_owned());
+        }
+        // Larger factorials do not fit in an `f64`.
+        if self.value > 170.0 {
+            return Err("That factorial is too large to calculate.".to_owned());
+        }
+
+        Ok(Quantity::number((1..self.value as u32 + 1).fold(1.0, |acc, n| acc * n as f64)))
+    }
+}
+
+fn units_too_large() -> String {
+    "The powers of the units of the result are too large.".to_owned()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match *self {
+            Token::Number(n) => format!("\"{}\"", format_number(n)),
+            Token::Ident(ref name) => format!("\"{}\"", name),
+            Token::Op(op) => format!("\"{}\"", op),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    // Whether each currently open parenthesis begins a function's arguments.
+    let mut calls = Vec::new();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == '°' {
+            chars.next();
+        } else if c.is_digit(10) || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_digit(10) || c == '.' {
+                    number.push(c);
+                    chars.next();
+                } else if c == ',' && !number.is_empty() && !number.contains('.') &&
+                          !calls.contains(&true) {
+                    // Allow thousands separators outside of function arguments,
+                    // where commas separate the arguments instead.
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    let digits = lookahead.take_while(|d| d.is_digit(10)).count();
+                    if digits != 3 {
+                        break;
+                    }
+                    chars.next();
+                } else if c == 'e' || c == 'E' {
+                    // Only treat this as an exponent if it is followed by
+                    // digits, as it may otherwise be the constant `e`.
+                    let mut lookahead = chars.clone();
+                    lookahead.next();
+                    let mut exponent = c.to_string();
+                    if let Some(&sign) = lookahead.peek() {
+                        if sign == '+' || sign == '-' {
+                            exponent.push(sign);
+                            lookahead.next();
+                        }
+                    }
+                    match lookahead.peek() {
+                        Some(d) if d.is_digit(10) => {},
+                        _ => break,
+                    }
+                    number.push_str(&exponent);
+                    chars = lookahead;
+                    while let Some(&d) = chars.peek() {
+                        if !d.is_digit(10) {
+                            break;
+                        }
+                        number.push(d);
+                        chars.next();
+                    }
+                    break;
+                } else {
+                    break;
+                }
+            }
+            let value = number.parse::<f64>()
+                .map_err(|_| format!("Invalid number: \"{}\"", number))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(name));
+        } else {
+            chars.next();
+            let op = match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    '^'
+                },
+                '×' | '·' => '*',
+                '÷' => '/',
+                '−' => '-',
+                '+' | '-' | '*' | '/' | '%' | '^' | '!' | '(' | ')' | ',' | '=' => c,
+                _ => return Err(format!("Unexpected character: \"{}\"", c)),
+            };
+            match op {
+                '(' => {
+                    calls.push(match tokens.last() {
+                        Some(&Token::Ident(ref name)) => FUNCTIONS.contains(&name.as_str()),
+                        _ => false,
+                    })
+                },
+                ')' => {
+                    calls.pop();
+                },
+                _ => {},
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// The maximum depth to which expressions may be nested.
+const MAX_DEPTH: usize = 64;
+
+// A recursive descent parser, which evaluates an expression as it is parsed.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    variables: &'a HashMap<String, Quantity>,
+    // The current depth of recursion, limited so that deeply nested input
+    // cannot overflow the stack.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token], variables: &'a HashMap<String, Quantity>) -> Self {
+        Parser {
+            tokens: tokens,
+            pos: 0,
+            variables: variables,
+            depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(o)) if o == op => Ok(()),
+            Some(token) => Err(format!("Expected \"{}\" but found {}.", op, token.describe())),
+            None => Err(format!("Expected \"{}\" but reached the end of the expression.", op)),
+        }
+    }
+
+    // expression = term (("+" | "-") term)*
+    //
+    // Temperatures on scales which do not start at absolute zero, e.g. `10 C`,
+    // may be subtracted from each other to give a difference, but not added.
+    fn expression(&mut self) -> Result<Quantity, String> {
+        let start = self.pos;
+        let mut left = self.term()?;
+        let mut absolute = self.is_offset_temperature(start);
+        loop {
+            match self.peek() {
+                Some(Token::Op('+')) => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    let right = self.term()?;
+                    let right_absolute = self.is_offset_temperature(start);
+                    if absolute && right_absolute {
+                        return Err("Cannot add two Celsius or Fahrenheit temperatures. Use K \
+                                    for temperature differences."
+                            .to_owned());
+                    }
+                    left = left.add(right)?;
+                    absolute = absolute || right_absolute;
+                },
+                Some(Token::Op('-')) => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    left = left.add(self.term()?.negate())?;
+                    absolute = absolute != self.is_offset_temperature(start);
+                },
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    // Checks whether the tokens from `start` up to the current position are a
+    // single temperature in a unit with an offset, e.g. `-40 C`.
+    fn is_offset_temperature(&self, start: usize) -> bool {
+        let start = match self.tokens.get(start) {
+            Some(&Token::Op('-')) | Some(&Token::Op('+')) => start + 1,
+            _ => start,
+        };
+        let number = match self.tokens.get(start) {
+            Some(&Token::Number(_)) => true,
+            _ => false,
+        };
+
+        number && self.pos == start + 2 && self.has_offset_unit(start + 1)
+    }
+
+    // Returns the unit named by the token at `pos`, unless the name belongs to
+    // a variable.
+    fn unit_at(&self, pos: usize) -> Option<&'static Unit> {
+        match self.tokens.get(pos) {
+            Some(&Token::Ident(ref name)) if !self.variables.contains_key(name) => find_unit(name),
+            _ => None,
+        }
+    }
+
+    fn has_offset_unit(&self, pos: usize) -> bool {
+        self.unit_at(pos).map_or(false, |unit| unit.offset != 0.0)
+    }
+
+    // term = unary (("*" | "/" | "%") unary | unary)*
+    fn term(&mut self) -> Result<Quantity, String> {
+        let mut left = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Op('*')) => {
+                    self.pos += 1;
+                    left = left.multiply(self.unary()?)?;
+                },
+                Some(Token::Op('/')) => {
+                    self.pos += 1;
+                    left = left.divide(self.unary()?)?;
+                },
+                Some(Token::Op('%')) => {
+                    self.pos += 1;
+                    left = left.remainder(self.unary()?)?;
+                },
+                // Implicit multiplication, e.g. `2 pi` or `3(4 + 5)`.
+                Some(Token::Ident(ref name)) if !is_conversion_keyword(name) => {
+                    left = left.multiply(self.unary()?)?;
+                },
+                Some(Token::Op('(')) => left = left.multiply(self.unary()?)?,
+                _ => return Ok(left),
+            }
+        }
+    }
+
+    // unary = ("-" | "+") unary | power
+    //
+    // Every form of nesting (parentheses, function arguments, exponents and
+    // repeated signs) recurses through here, so this is where the depth of
+    // recursion is limited.
+    fn unary(&mut self) -> Result<Quantity, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("The expression is nested too deeply.".to_owned());
+        }
+
+        self.depth += 1;
+        let result = self.signed();
+        self.depth -= 1;
+        result
+    }
+
+    fn signed(&mut self) -> Result<Quantity, String> {
+        match self.peek() {
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                // The sign of a temperature belongs to its number, as `-40 C`
+                // is forty degrees below zero rather than the negation of
+                // forty degrees above it.
+                if let Some(Token::Number(value)) = self.peek() {
+                    if self.has_offset_unit(self.pos + 1) {
+                        self.pos += 1;
+                        return self.number_with_unit(-value);
+                    }
+                }
+                Ok(self.unary()?.negate())
+            },
+            Some(Token::Op('+')) => {
+                self.pos += 1;
+                self.unary()
+            },
+            _ => self.power(),
+        }
+    }
+
+    // power = postfix ("^" unary)?
+    fn power(&mut self) -> Result<Quantity, String> {
+        let base = self.postfix()?;
+        match self.peek() {
+            Some(Token::Op('^')) => {
+                self.pos += 1;
+                base.pow(self.unary()?)
+            },
+            _ => Ok(base),
+        }
+    }
+
+    // postfix = primary "!"*
+    fn postfix(&mut self) -> Result<Quantity, String> {
+        let mut value = self.primary()?;
+        while let Some(Token::Op('!')) = self.peek() {
+            self.pos += 1;
+            value = value.factorial()?;
+        }
+        Ok(value)
+    }
+
+    // primary = number unit? | function "(" arguments ")" | identifier
+    //         | "(" expression ")"
+    fn primary(&mut self) -> Result<Quantity, String> {
+        match self.next() {
+            Some(Token::Number(value)) => self.number_with_unit(value),
+            Some(Token::Ident(ref name)) if FUNCTIONS.contains(&name.as_str()) => {
+                self.expect('(')?;
+                let mut arguments = vec![self.expression()?];
+                while let Some(Token::Op(',')) = self.peek() {
+                    self.pos += 1;
+                    arguments.push(self.expression()?);
+                }
+                self.expect(')')?;
+                call(name, &arguments)
+            },
+            Some(Token::Ident(ref name)) => self.identifier(name),
+            Some(Token::Op('(')) => {
+                let value = self.expression()?;
+                self.expect(')')?;
+                Ok(value)
+            },
+            Some(token) => Err(format!("Unexpected {}.", token.describe())),
+            None => Err("Unexpected end of expression.".to_owned()),
+        }
+    }
+
+    // Binds a number to the unit which immediately follows it, so that
+    // `5 km / 20 min` divides by twenty minutes and `100 F` is an absolute
+    // temperature. Units take precedence over constants here, e.g. `100 c` is
+    // a temperature rather than a multiple of the speed of light.
+    fn number_with_unit(&mut self, value: f64) -> Result<Quantity, String> {
+        let unit = match self.unit_at(self.pos) {
+            Some(unit) => unit,
+            None => return Ok(Quantity::number(value)),
+        };
+        self.pos += 1;
+
+        // Allow powers of the unit alone, e.g. `5 m^2`.
+        if let Some(Token::Op('^')) = self.peek() {
+            let exponent = match (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)) {
+                (Some(&Token::Number(n)), _) => Some((n, 2)),
+                (Some(&Token::Op('-')), Some(&Token::Number(n))) => Some((-n, 3)),
+                _ => None,
+            };
+            if let Some((exponent, length)) = exponent {
+                self.pos += length;
+                let unit = Quantity {
+                    value: unit.factor,
+                    dimension: unit.dimension,
+                };
+                return Quantity::number(value).multiply(unit.pow(Quantity::number(exponent))?);
+            }
+        }
+
+        Ok(Quantity {
+            value: value * unit.factor + unit.offset,
+            dimension: unit.dimension,
+        })
+    }
+
+    fn identifier(&self, name: &str) -> Result<Quantity, String> {
+        if let Some(value) = self.variables.get(name) {
+            return Ok(*value);
+        }
+        if let Some(constant) = find_constant(name) {
+            return Ok(constant);
+        }
+        if let Some(unit) = find_unit(name) {
+            return Ok(Quantity {
+                value: unit.factor,
+                dimension: unit.dimension,
+            });
+        }
+
+        Err(format!("Unknown variable, constant or unit: \"{}\"", name))
+    }
+}
+
+fn is_conversion_keyword(name: &str) -> bool {
+    CONVERSION_KEYWORDS.contains(&name)
+}
+
+fn find_constant(name: &str) -> Option<Quantity> {
+    CONSTANTS.iter()
+        .find(|&&(n, _, _)| n == name)
+        .map(|&(_, value, dimension)| {
+            Quantity {
+                value: value,
+                dimension: dimension,
+            }
+        })
+}
+
+// Finds a unit by name, preferring an exact match, as some unit symbols
+// differ only in case.
+fn find_unit(name: &str) -> Option<&'static Unit> {
+    UNITS.iter()
+        .find(|unit| unit.names.contains(&name))
+        .or_else(|| {
+            let name = name.to_lowercase();
+            UNITS.iter().find(|unit| unit.names.iter().any(|n| n.to_lowercase() == name))
+        })
+}
+
+fn call(name: &str, arguments: &[Quantity]) -> Result<Quantity, String> {
+    let (min_arguments, max_arguments) = match name {
+        "log" => (1, 2),
+        "min" | "max" => (1, usize::max_value()),
+        _ => (1, 1),
+    };
+    if arguments.len() < min_arguments || arguments.len() > max_arguments {
+        return Err(format!("Wrong number of arguments for {}.", name));
+    }
+
+    let x = arguments[0];
+    match name {
+        // These functions preserve the units of their argument.
+        "abs" => return Ok(Quantity { value: x.value.abs(), ..x }),
+        "floor" => return Ok(Quantity { value: x.value.floor(), ..x }),
+        "ceil" => return Ok(Quantity { value: x.value.ceil(), ..x }),
+        "round" => return Ok(Quantity { value: x.value.round(), ..x }),
+        "min" | "max" => {
+            let mut result = x;
+            for argument in &arguments[1..] {
+                if argument.dimension != x.dimension {
+                    return Err(format!("All arguments to {} must have the same units.", name));
+                }
+                if (name == "min") == (argument.value < result.value) {
+                    result = *argument;
+                }
+            }
+            return Ok(result);
+        },
+        "sqrt" => {
+            if x.dimension.iter().any(|d| d % 2 != 0) {
+                return Err("Cannot take the square root of those units.".to_owned());
+            }
+            let mut dimension = x.dimension;
+            for d in &mut dimension {
+                *d /= 2;
+            }
+            return Ok(Quantity {
+                value: x.value.sqrt(),
+                dimension: dimension,
+            });
+        },
+        _ => {},
+    }
+
+    if arguments.iter().any(|argument| !argument.is_dimensionless()) {
+        return Err(format!("The argument to {} cannot have units.", name));
+    }
+    let value = match name {
+        "sin" => x.value.sin(),
+        "cos" => x.value.cos(),
+        "tan" => x.value.tan(),
+        "asin" => x.value.asin(),
+        "acos" => x.value.acos(),
+        "atan" => x.value.atan(),
+        "sinh" => x.value.sinh(),
+        "cosh" => x.value.cosh(),
+        "tanh" => x.value.tanh(),
+        "cbrt" => x.value.cbrt(),
+        "exp" => x.value.exp(),
+        "ln" => x.value.ln(),
+        "log" if arguments.len() == 2 => x.value.log(arguments[1].value),
+        "log" => x.value.log10(),
+        "log2" => x.value.log2(),
+        _ => return Err(format!("Unknown function: {}", name)),
+    };
+
+    Ok(Quantity::number(value))
+}
+
+// The result of evaluating a line of input.
+#[derive(Debug)]
+struct Evaluation {
+    // The name of the variable being assigned to, if any.
+    assignment: Option<String>,
+    quantity: Quantity,
+    display: String,
+}
+
+fn evaluate_with(
+    input: &str,
+    variables: &HashMap<String, Quantity>
+) -> Result<Evaluation, String> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Err("Please specify an expression to calculate.".to_owned());
+    }
+
+    // Check for an assignment, e.g. `x = 2`.
+    let (assignment, start) = match (tokens.first(), tokens.get(1)) {
+        (Some(&Token::Ident(ref name)), Some(&Token::Op('='))) => {
+            validate_variable_name(name)?;
+            (Some(name.clone()), 2)
+        },
+        _ => (None, 0),
+    };
+
+    let mut parser = Parser::new(&tokens[start..], variables);
+    let quantity = parser.expression()?;
+    if !quantity.value.is_finite() {
+        return Err("The result is not a finite number.".to_owned());
+    }
+
+    let display = match parser.next() {
+        None => format_quantity(quantity),
+        Some(Token::Ident(ref keyword)) if is_conversion_keyword(keyword) => {
+            convert(quantity, &parser.tokens[parser.pos..], variables)?
+        },
+        Some(token) => return Err(format!("Unexpected {}.", token.describe())),
+    };
+
+    Ok(Evaluation {
+        assignment: assignment,
+        quantity: quantity,
+        display: display,
+    })
+}
+
+/// Evaluates an expression without any variables, returning the formatted
+/// result.
+pub fn evaluate(input: &str) -> Result<String, String> {
+    evaluate_with(input, &HashMap::new()).map(|evaluation| evaluation.display)
+}
+
+// Converts a quantity to the units given by the remaining tokens, e.g.
+// `miles` or `km/h`.
+fn convert(
+    quantity: Quantity,
+    tokens: &[Token],
+    variables: &HashMap<String, Quantity>
+) -> Result<String, String> {
+    let target = tokens.iter()
+        .enumerate()
+        .map(|(i, token)| {
+            let text = match *token {
+                Token::Number(n) => format_number(n),
+                Token::Ident(ref name) => name.clone(),
+                Token::Op(op) => op.to_string(),
+            };
+            // Separate adjacent words and numbers.
+            match (i.checked_sub(1).and_then(|i| tokens.get(i)), token) {
+                (Some(&Token::Op(_)), _) | (_, &Token::Op(_)) | (None, _) => text,
+                _ => format!(" {}", text),
+            }
+        })
+        .collect::<String>();
+
+    // Temperature scales do not start at zero, so they cannot be treated as
+    // ordinary factors.
+    let unit = match tokens.first() {
+        Some(&Token::Ident(ref name)) if tokens.len() == 1 => find_unit(name),
+        _ => None,
+    };
+    let (factor, offset, dimension) = match unit {
+        Some(unit) => (unit.factor, unit.offset, unit.dimension),
+        None => {
+            let mut parser = Parser::new(tokens, variables);
+            let unit = parser.expression()?;
+            if let Some(token) = parser.next() {
+                return Err(format!("Unexpected {}.", token.describe()));
+            }
+            if unit.value == 0.0 {
+                return Err("Cannot convert to a unit of zero size.".to_owned());
+            }
+            (unit.value, 0.0, unit.dimension)
+        },
+    };
+
+    if quantity.dimension != dimension {
+        return Err(format!("Cannot convert {} to {}.",
+                           describe_dimension(quantity.dimension),
+                           describe_dimension(dimension)));
+    }
+
+    Ok(format!("{} {}", format_number((quantity.value - offset) / factor), target))
+}
+
+fn validate_variable_name(name: &str) -> Result<(), String> {
+    if is_conversion_keyword(name) || FUNCTIONS.contains(&name) {
+        return Err(format!("\"{}\" cannot be used as a variable name.", name));
+    }
+    if find_constant(name).is_some() {
+        return Err(format!("\"{}\" is a constant, and cannot be changed.", name));
+    }
+    if UNITS.iter().any(|unit| unit.names.contains(&name)) {
+        return Err(format!("\"{}\" is the name of a unit, and cannot be used as a variable \
+                            name.",
+                           name));
+    }
+
+    Ok(())
+}
+
+// Formats a number with up to 12 significant digits, using scientific
+// notation for very large or very small numbers.
+fn format_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_owned();
+    }
+
+    let magnitude = value.abs();
+    let exponent = magnitude.log10().floor() as i32;
+    if magnitude >= 1e15 || magnitude < 1e-6 {
+        let mantissa = value / 10f64.powi(exponent);
+        return format!("{}e{}", trim_decimals(format!("{:.9}", mantissa)), exponent);
+    }
+
+    let decimals = (11 - exponent).max(0).min(15) as usize;
+    trim_decimals(format!("{:.*}", decimals, value))
+}
+
+fn trim_decimals(number: String) -> String {
+    if !number.contains('.') {
+        return number;
+    }
+
+    number.trim_right_matches('0').trim_right_matches('.').to_owned()
+}
+
+// Formats a quantity in SI base units, e.g. `12.5 m/s`.
+fn format_quantity(quantity: Quantity) -> String {
+    let number = format_number(quantity.value);
+    if quantity.is_dimensionless() {
+        return number;
+    }
+
+    let unit_power = |unit: &str, power: i32| if power == 1 {
+        unit.to_owned()
+    } else {
+        format!("{}^{}", unit, power)
+    };
+    let numerator = BASE_UNITS.iter()
+        .zip(quantity.dimension.iter())
+        .filter(|&(_, &power)| power > 0)
+        .map(|(unit, &power)| unit_power(unit, power))
+        .collect::<Vec<String>>();
+    let denominator = BASE_UNITS.iter()
+        .zip(quantity.dimension.iter())
+        .filter(|&(_, &power)| power < 0)
+        .map(|(unit, &power)| if numerator.is_empty() {
+            unit_power(unit, power)
+        } else {
+            unit_power(unit, -power)
+        })
+        .collect::<Vec<String>>();
+
+    let units = match (numerator.is_empty(), denominator.is_empty()) {
+        (false, true) => numerator.join("*"),
+        (true, false) => denominator.join("*"),
+        _ => format!("{}/{}", numerator.join("*"), denominator.join("*")),
+    };
+
+    format!("{} {}", number, units)
+}
+
+// Describes a dimension for use in error messages, e.g. "length".
+fn describe_dimension(dimension: Dimension) -> String {
+    match DIMENSION_NAMES.iter().find(|&&(d, _)| d == dimension) {
+        Some(&(_, name)) => name.to_owned(),
+        None => {
+            let quantity = Quantity {
+                value: 1.0,
+                dimension: dimension,
+            };
+            format!("units of {}", format_quantity(quantity).trim_left_matches("1 "))
+        },
+    }
+}
+
+command!(calc(context, message, args) {
+    let mut variables = VARIABLES.lock().expect("Failed to lock calculator variables");
+
+    match args.first().map(String::as_ref) {
+        None => {
+            return Err("Please specify an expression to calculate, e.g. `5 km in miles`."
+                .to_owned())
+        },
+        Some("vars") if args.len() == 1 => {
+            let response = match variables.get(&message.author.id) {
+                Some(vars) if !vars.is_empty() => {
+                    let mut names = vars.keys().collect::<Vec<&String>>();
+                    names.sort();
+                    names.iter()
+                        .map(|name| format!("{} = {}", name, format_quantity(vars[*name])))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                },
+                _ => "You have not defined any variables.".to_owned(),
+            };
+            check_msg(context.say(&response));
+            return Ok(());
+        },
+        Some("clear") if args.len() == 1 => {
+            variables.remove(&message.author.id);
+            check_msg(context.say("Cleared your variables."));
+            return Ok(());
+        },
+        _ => {},
+    }
+
+    let vars = variables.entry(message.author.id).or_insert_with(HashMap::new);
+    let evaluation = evaluate_with(&args.join(" "), vars)?;
+    let response = remember(vars, evaluation);
+    check_msg(context.say(&response));
+});
+
+// Stores the result of an evaluation as `ans`, and in the variable it was
+// assigned to if any, returning the response to it.
+fn remember(variables: &mut HashMap<String, Quantity>, evaluation: Evaluation) -> String {
+    variables.insert("ans".to_owned(), evaluation.quantity);
+
+    match evaluation.assignment {
+        Some(name) => {
+            let response = format!("{} = {}", name, evaluation.display);
+            variables.insert(name, evaluation.quantity);
+            response
+        },
+        None => evaluation.display,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::iter;
+    use super::{evaluate, evaluate_with, remember};
+
+    fn repeat(text: &str, count: usize) -> String {
+        iter::repeat(text).take(count).collect()
+    }
+
+    #[test]
+    fn test_unit_conversions() {
+        assert_eq!(evaluate("5 km in miles").unwrap(), "3.10685596119 miles");
+        assert_eq!(evaluate("100 F to C").unwrap(), "37.7777777778 C");
+        assert_eq!(evaluate("0 F to C").unwrap(), "-17.7777777778 C");
+        assert_eq!(evaluate("5 km / 20 min in km/h").unwrap(), "15 km/h");
+        assert_eq!(evaluate("3 m * 2 m").unwrap(), "6 m^2");
+    }
+
+    #[test]
+    fn test_temperatures() {
+        assert_eq!(evaluate("-40 C to F").unwrap(), "-40 F");
+        assert_eq!(evaluate("-10 C").unwrap(), "263.15 K");
+        assert_eq!(evaluate("-10 C in C").unwrap(), "-10 C");
+        // Differences between temperatures are in kelvin.
+        assert_eq!(evaluate("10 C - 5 C").unwrap(), "5 K");
+        assert_eq!(evaluate("10 C + 5 K to C").unwrap(), "15 C");
+        assert!(evaluate("10 C + 5 C").is_err());
+        assert!(evaluate("10 C + 5 K + 5 C").is_err());
+    }
+
+    #[test]
+    fn test_functions_and_constants() {
+        assert_eq!(evaluate("sin(pi/2)").unwrap(), "1");
+        assert_eq!(evaluate("max(1, 3, 2)").unwrap(), "3");
+        assert_eq!(evaluate("5!").unwrap(), "120");
+    }
+
+    #[test]
+    fn test_precedence() {
+        // Exponentiation is right-associative, and binds tighter than negation.
+        assert_eq!(evaluate("2^3^2").unwrap(), "512");
+        assert_eq!(evaluate("-2^2").unwrap(), "-4");
+        assert_eq!(evaluate("2^-1").unwrap(), "0.5");
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), "7");
+    }
+
+    #[test]
+    fn test_implicit_multiplication() {
+        assert_eq!(evaluate("2 pi").unwrap(), evaluate("2 * pi").unwrap());
+        assert_eq!(evaluate("3(4+5)").unwrap(), "27");
+    }
+
+    #[test]
+    fn test_thousands_separators() {
+        assert_eq!(evaluate("1,000 + 1").unwrap(), "1001");
+        assert_eq!(evaluate("1,000,000 / 2").unwrap(), "500000");
+        assert!(evaluate("1,00 + 1").is_err());
+        // Commas separate function arguments instead.
+        assert_eq!(evaluate("max(1, 2)").unwrap(), "2");
+        assert_eq!(evaluate("max(1,2)").unwrap(), "2");
+        assert_eq!(evaluate("max(1,000)").unwrap(), "1");
+        assert_eq!(evaluate("log(8,2)").unwrap(), "3");
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut variables = HashMap::new();
+
+        let evaluation = evaluate_with("x = 2", &variables).unwrap();
+        assert_eq!(remember(&mut variables, evaluation), "x = 2");
+        let evaluation = evaluate_with("ans", &variables).unwrap();
+        assert_eq!(remember(&mut variables, evaluation), "2");
+        let evaluation = evaluate_with("ans * x + 1", &variables).unwrap();
+        assert_eq!(remember(&mut variables, evaluation), "5");
+        assert_eq!(evaluate_with("ans", &variables).unwrap().display, "5");
+
+        assert!(evaluate("ans").is_err());
+        assert!(evaluate_with("pi = 3", &variables).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(evaluate("5 km + 3 kg").unwrap_err(), "Cannot add or subtract length and mass.");
+        assert_eq!(evaluate("1 / 0").unwrap_err(), "Division by zero.");
+        assert_eq!(evaluate("2 foo").unwrap_err(),
+                   "Unknown variable, constant or unit: \"foo\"");
+        assert_eq!(evaluate("5 km in kg").unwrap_err(), "Cannot convert length to mass.");
+    }
+
+    #[test]
+    fn test_dimension_overflow() {
+        assert_eq!(evaluate("m^2147483647 * m").unwrap_err(),
+                   "The powers of the units of the result are too large.");
+        assert_eq!(evaluate("m^1e10").unwrap_err(),
+                   "The powers of the units of the result are too large.");
+        assert_eq!(evaluate("(m^65536)^65536").unwrap_err(),
+                   "The powers of the units of the result are too large.");
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = format!("{}1{}", repeat("(", 100), repeat(")", 100));
+        assert_eq!(evaluate(&nested).unwrap_err(), "The expression is nested too deeply.");
+        assert_eq!(evaluate(&format!("{}1", repeat("-", 100))).unwrap_err(),
+                   "The expression is nested too deeply.");
+
+        let nested = format!("{}1{}", repeat("(", 10), repeat(")", 10));
+        assert_eq!(evaluate(&nested).unwrap(), "1");
+    }
+}
@@ -0,0 +1,10 @@
+/// A Markov chain of words, trained on lines of text.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Chain {
+    // The number of preceding words used to choose the next one.
+    order: usize,
+    // The number of times each word has followed each state, where a state is
+    // the preceding words joined by spaces. The empty word marks the start and
+    // end of a line.
+    transitions: HashMap<String, HashMap<String, u64>>,
+}
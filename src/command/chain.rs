@@ -0,0 +1,183 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides a [Markov chain][markov-chain] of words, which generates text
+//! resembling the lines it was trained on.
+//!
+//! Chains can be saved to and loaded from disk, so that large corpora need not
+//! be retrained every time the bot starts.
+//!
+//! [markov-chain]: https://en.wikipedia.org/wiki/Markov_chain
+
+use error::Result;
+use rand::{self, Rng};
+use serde_json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+// Marks the start and end of a line.
+const BOUNDARY: &'static str = "";
+
+// The maximum number of words generated at once, in case the chain loops.
+const MAX_WORDS: usize = 1000;
+
+#[cfg(feature = "nightly")]
+include!("chain.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/chain.rs"));
+
+impl Chain {
+    /// Returns an empty chain which chooses each word based on the `order`
+    /// words before it.
+    pub fn of_order(order: usize) -> Self {
+        assert!(order > 0, "The order of a Markov chain must be at least 1");
+
+        Chain {
+            order: order,
+            transitions: HashMap::new(),
+        }
+    }
+
+    /// Trains the chain on a line of text.
+    pub fn feed_str(&mut self, line: &str) {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        if words.is_empty() {
+            return;
+        }
+
+        let mut state = vec![BOUNDARY; self.order];
+        for word in words.into_iter().chain(Some(BOUNDARY)) {
+            *self.transitions
+                .entry(state.join(" "))
+                .or_insert_with(HashMap::new)
+                .entry(word.to_owned())
+                .or_insert(0) += 1;
+            state.remove(0);
+            state.push(word);
+        }
+    }
+
+    /// Generates a line of text, which is empty if the chain is untrained.
+    pub fn generate_str(&self) -> String {
+        self.generate_from(vec![BOUNDARY.to_owned(); self.order], Vec::new())
+    }
+
+    /// Generates a line of text starting with `token`, which is empty unless
+    /// the chain has seen a line start with it (or, for chains of order 1, has
+    /// seen it at all).
+    pub fn generate_str_from_token(&self, token: &str) -> String {
+        let mut state = vec![BOUNDARY.to_owned(); self.order - 1];
+        state.push(token.to_owned());
+        if !self.transitions.contains_key(&state.join(" ")) {
+            return String::new();
+        }
+
+        self.generate_from(state, vec![token.to_owned()])
+    }
+
+    fn generate_from(&self, mut state: Vec<String>, mut words: Vec<String>) -> String {
+        let mut rng = rand::thread_rng();
+        while words.len() < MAX_WORDS {
+            let next = match self.transitions
+                .get(&state.join(" "))
+                .and_then(|counts| choose_weighted(&mut rng, counts)) {
+                Some(word) if word != BOUNDARY => word.clone(),
+                _ => break,
+            };
+            state.remove(0);
+            state.push(next.clone());
+            words.push(next);
+        }
+
+        words.join(" ")
+    }
+
+    /// Loads a chain saved with `save`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Saves the chain to a file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(serde_json::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+// Chooses a word at random, in proportion to the number of times it was seen.
+fn choose_weighted<'a, R>(rng: &mut R, counts: &'a HashMap<String, u64>) -> Option<&'a String>
+    where R: Rng,
+{
+    let total = counts.values().fold(0, |total, count| total + count);
+    if total == 0 {
+        return None;
+    }
+
+    let mut pick = rng.gen_range(0, total);
+    for (word, &count) in counts {
+        if pick < count {
+            return Some(word);
+        }
+        pick -= count;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::Chain;
+
+    #[test]
+    fn test_generate_single_line() {
+        let mut chain = Chain::of_order(1);
+        assert_eq!(chain.generate_str(), "");
+
+        chain.feed_str("the  quick fox");
+        assert_eq!(chain.generate_str(), "the quick fox");
+        assert_eq!(chain.generate_str_from_token("quick"), "quick fox");
+        assert_eq!(chain.generate_str_from_token("dog"), "");
+    }
+
+    #[test]
+    fn test_generate_higher_order() {
+        let mut chain = Chain::of_order(2);
+        chain.feed_str("a b c");
+        chain.feed_str("x b d");
+
+        // With two words of context, the lines cannot be mixed.
+        for _ in 0..20 {
+            let text = chain.generate_str();
+            assert!(text == "a b c" || text == "x b d", "Unexpected text: {}", text);
+        }
+        assert_eq!(chain.generate_str_from_token("x"), "x b d");
+        assert_eq!(chain.generate_str_from_token("b"), "");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join("smexybot-chain-test.chain");
+        let mut chain = Chain::of_order(1);
+        chain.feed_str("hello world");
+        chain.save(&path).unwrap();
+
+        let loaded = Chain::load(&path).unwrap();
+        assert_eq!(loaded.order, 1);
+        assert_eq!(loaded.generate_str(), "hello world");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,22 @@
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Settings {
+    /// The corpora which messages are learned into, by channel ID.
+    learning: HashMap<String, String>,
+    /// The IDs of the users who have opted out of learning.
+    optouts: HashSet<u64>,
+    /// The IDs of the users who have consented to being impersonated, by
+    /// guild ID.
+    #[serde(default)]
+    guild_consents: HashMap<String, HashSet<u64>>,
+    /// The chat mode settings, by channel ID.
+    #[serde(default)]
+    chat: HashMap<String, ChatSettings>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ChatSettings {
+    /// The corpus which replies are generated from.
+    corpus: String,
+    /// The probability of replying when the bot is mentioned.
+    probability: f64,
+}
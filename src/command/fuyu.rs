@@ -7,58 +7,1144 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! Provides the functionality for a `!fuyu` command, which first reads in a
-//! double newline delimited file, then uses that to generate a
-//! [Markov chain][markov-chain] out of the data using the [markov][markov-lib]
-//! library. It then returns a message generated by the Markov chain.
+//! Provides the functionality for the `!markov` and `!fuyu` commands, which
+//! first read in a double newline delimited file (a corpus), then use that to
+//! generate a [Markov chain][markov-chain] out of the data. They then return a
+//! message generated by the Markov chain.
+//!
+//! Corpora are registered in the bot's config, and may either be available
+//! everywhere or owned by a single guild. The `!fuyu` command is an alias for
+//! generating from the default corpus.
+//!
+//! Guild administrators may also enable learning in a channel, in which case
+//! messages sent there are fed into one of the guild's corpora as they arrive,
+//! unless their author has opted out.
+//!
+//! Users may also consent to being impersonated in a guild, in which case the
+//! messages they send in that guild's channels with learning enabled are
+//! additionally collected into a corpus of their own, which `!fuyu @user`
+//! generates from within that guild only. A user's corpus is deleted when they
+//! withdraw their consent. Lines already learned into the guild's shared
+//! corpora are kept, as those corpora do not record who wrote each line.
+//!
+//! Finally, chat mode may be enabled in a channel, in which case the bot
+//! replies to messages mentioning or replying to it with text generated from a
+//! corpus, seeded with a word from the message where possible. The messages
+//! Discord sends to bots do not record which message they respond to, so a
+//! message is taken to reply to the bot when the message before it in the
+//! channel was the bot's.
+//!
+//! Each chain is trained once and kept in memory, and is only retrained when
+//! its corpus changes. If a cache directory is configured, trained chains are
+//! also saved to disk so that they need not be retrained on startup.
 //!
-//! [markov-lib]: https://github.com/aatxe/markov
 //! [markov-chain]: https://en.wikipedia.org/wiki/Markov_chain
 
-extern crate markov;
+extern crate regex;
+
+use ::CONFIG;
+use command::chain::Chain;
+use config::CorpusConfig;
+use job;
+use rand::{self, Rng};
+use self::regex::Regex;
+use serenity::client::{CACHE, Context};
+use serenity::model::{ChannelId, GuildId, Message, UserId};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use store::Store;
+use util::{check_msg, is_admin, random_colour};
+
+lazy_static! {
+    static ref CHAINS: Mutex<HashMap<String, Arc<Mutex<Option<Corpus>>>>> =
+        Mutex::new(HashMap::new());
+    static ref LAST_AUTHORS: Mutex<HashMap<ChannelId, UserId>> = Mutex::new(HashMap::new());
+    static ref CHAT_COOLDOWNS: Mutex<HashMap<ChannelId, Instant>> = Mutex::new(HashMap::new());
+    static ref USER_MENTION_REGEX: Regex = Regex::new(r"^<@!?(\d+)>$").unwrap();
+    static ref MENTION_REGEX: Regex = Regex::new(r"<(@[!&]?|#)\d+>|@everyone|@here").unwrap();
+    static ref BLOCKLIST_REGEX: Option<Regex> = blocklist_regex(&CONFIG.markov.blocklist);
+    static ref SETTINGS: Mutex<Store<Settings>> = Mutex::new(Store::new("markov.json"));
+    static ref LEARNER: Mutex<Sender<Lesson>> = Mutex::new(start_learner());
+}
+
+#[cfg(feature = "nightly")]
+include!("fuyu.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/fuyu.rs"));
+
+// A trained chain, along with information about the corpus it was trained on.
+struct Corpus {
+    chain: Chain,
+    order: usize,
+    // The modification time of the corpus file when the chain was trained.
+    modified: Option<SystemTime>,
+    lines: usize,
+    words: usize,
+    vocabulary: HashSet<String>,
+    // The lines of the corpus, with their whitespace normalized, used to reject
+    // generated text which reproduces the corpus verbatim.
+    sources: HashSet<String>,
+}
+
+// A line waiting to be learned into a corpus by the learner thread.
+struct Lesson {
+    name: String,
+    config: CorpusConfig,
+    line: String,
+    // The user whose own corpus this is. Their consent is checked again before
+    // the line is learned, as it may have been withdrawn since it was queued.
+    owner: Option<(GuildId, UserId)>,
+}
+
+// Options controlling the text generated from a corpus.
+#[derive(Debug, Default)]
+struct GenerateOptions {
+    // A word which the generated text should start from.
+    seed: Option<String>,
+    min_words: Option<usize>,
+    max_words: Option<usize>,
+}
+
+impl GenerateOptions {
+    // Parses options of the form `[about <word>] [--min <n>] [--max <n>]`.
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut options = GenerateOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "about" => {
+                    match args.next() {
+                        Some(word) => options.seed = Some(word),
+                        None => {
+                            return Err("Please specify a word to generate text about.".to_owned())
+                        },
+                    }
+                },
+                "--min" => options.min_words = Some(parse_word_count(args.next())?),
+                "--max" => options.max_words = Some(parse_word_count(args.next())?),
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        if let (Some(min), Some(max)) = (options.min_words, options.max_words) {
+            if min > max {
+                return Err("The minimum word count cannot exceed the maximum.".to_owned());
+            }
+        }
 
-use self::markov::Chain;
+        Ok(options)
+    }
+}
 
-use util::{check_msg, random_colour, stringify};
+fn parse_word_count(arg: Option<String>) -> Result<usize, String> {
+    match arg.as_ref().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(count)) if count > 0 => Ok(count),
+        _ => Err("Please specify a valid word count.".to_owned()),
+    }
+}
+
+impl Corpus {
+    fn new(order: usize) -> Self {
+        Corpus {
+            chain: Chain::of_order(order),
+            order: order,
+            modified: None,
+            lines: 0,
+            words: 0,
+            vocabulary: HashSet::new(),
+            sources: HashSet::new(),
+        }
+    }
+
+    // Updates the statistics of the corpus with a line, without training the
+    // chain on it.
+    fn count(&mut self, line: &str) {
+        self.lines += 1;
+        for word in line.split_whitespace() {
+            self.words += 1;
+            self.vocabulary.insert(word.to_owned());
+        }
+        self.sources.insert(normalize(line));
+    }
+
+    // Trains the chain on a line, and updates the statistics of the corpus.
+    fn feed(&mut self, line: &str) {
+        self.count(line);
+        self.chain.feed_str(line);
+    }
+}
+
+// The order of the chains trained on the corpora of individual users, which
+// are usually too small for higher orders to produce anything but verbatim
+// copies of their messages.
+const USER_CORPUS_ORDER: usize = 1;
+
+command!(fuyu(context, message, args) {
+    let result = match args.first().map(String::as_ref) {
+        Some("consent") => set_consent(context, message, true),
+        Some("forget") => set_consent(context, message, false),
+        Some(arg) if USER_MENTION_REGEX.is_match(arg) => {
+            impersonate(context, message, arg, args[1..].to_vec())
+        },
+        _ => generate(context, message, &CONFIG.markov.default_corpus, args),
+    };
+
+    match result {
+        Ok(()) => {},
+        v => return v,
+    }
+});
+
+command!(markov(context, message, args) {
+    let mut args = args.into_iter();
+
+    let result = match args.next().as_ref().map(String::as_ref) {
+        Some("list") => list(context, message),
+        Some("learn") => learn(context, message, args.next()),
+        Some("chat") => chat(context, message, args.collect()),
+        Some("optout") => set_optout(context, message, true),
+        Some("optin") => set_optout(context, message, false),
+        Some("info") => {
+            match args.next() {
+                Some(name) => info(context, message, &name),
+                None => Err("Please specify a corpus to get info on.".to_owned()),
+            }
+        },
+        // Generation options may be given without a corpus name, in which case
+        // the default corpus is used.
+        Some(option) if option == "about" || option.starts_with("--") => {
+            let args = Some(option.to_owned()).into_iter().chain(args).collect();
+            generate(context, message, &CONFIG.markov.default_corpus, args)
+        },
+        Some(name) => generate(context, message, name, args.collect()),
+        None => generate(context, message, &CONFIG.markov.default_corpus, Vec::new()),
+    };
+
+    match result {
+        Ok(()) => {},
+        v => return v,
+    }
+});
+
+/// Feeds messages sent in channels with learning enabled into the channel's
+/// corpus, and replies to messages mentioning or replying to the bot in
+/// channels with chat mode enabled.
+pub fn handle_message(message: &Message) {
+    let previous_author = LAST_AUTHORS.lock()
+        .expect("Failed to lock last message authors")
+        .insert(message.channel_id, message.author.id);
+
+    if message.author.bot || message.content.starts_with(&CONFIG.command_prefix) {
+        return;
+    }
+
+    learn_message(message);
+    reply_to_message(message, previous_author);
+}
+
+// Feeds a message into the corpus of its channel, and the corpus of its author
+// if they have consented to it.
+fn learn_message(message: &Message) {
+    let (name, consented) = {
+        let settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        let settings = settings.get();
+        if settings.optouts.contains(&message.author.id.0) {
+            return;
+        }
+        match settings.learning.get(&message.channel_id.to_string()) {
+            Some(name) => {
+                let consented = has_consented(settings, message.guild_id(), message.author.id);
+                (name.clone(), consented)
+            },
+            None => return,
+        }
+    };
+
+    let line = sanitize(&message.content);
+    if line.is_empty() {
+        return;
+    }
+
+    match find_corpus(&name, message.guild_id()) {
+        Ok(config) => {
+            queue_lesson(Lesson {
+                name: name,
+                config: config,
+                line: line.clone(),
+                owner: None,
+            })
+        },
+        Err(err) => warn!("Failed to learn message into corpus \"{}\": {}", name, err),
+    }
+
+    if let (true, Some(guild_id)) = (consented, message.guild_id()) {
+        let (name, config) = user_corpus(guild_id, message.author.id);
+        queue_lesson(Lesson {
+            name: name,
+            config: config,
+            line: line,
+            owner: Some((guild_id, message.author.id)),
+        });
+    }
+}
+
+// Hands a line over to the learner thread, so that writing it to the corpus
+// file and training the chain on it does not hold up the handling of events.
+fn queue_lesson(lesson: Lesson) {
+    let learner = LEARNER.lock().expect("Failed to lock Markov learner");
+    if learner.send(lesson).is_err() {
+        warn!("Markov learner thread has stopped; message not learned");
+    }
+}
+
+// Starts the thread which learns queued lines into their corpora.
+fn start_learner() -> Sender<Lesson> {
+    let (sender, receiver) = mpsc::channel::<Lesson>();
+    thread::spawn(move || {
+        for lesson in receiver {
+            if let Err(err) = learn_line(&lesson) {
+                warn!("Failed to learn message into corpus \"{}\": {}", lesson.name, err);
+            }
+        }
+    });
+
+    sender
+}
 
-command!(fuyu(context, message, _args) {
+// Replies to a message with generated text if it mentions the bot or follows
+// one of the bot's messages, and chat mode is enabled in its channel. The reply
+// is generated in the background, as training the chain may take a while and
+// would otherwise hold up the handling of every other event.
+fn reply_to_message(message: &Message, previous_author: Option<UserId>) {
+    let current_user_id = match CACHE.read() {
+        Ok(cache) => cache.user.id,
+        Err(_) => return,
+    };
+    let mentioned = message.mentions.iter().any(|user| user.id == current_user_id);
+    if !mentioned && previous_author != Some(current_user_id) {
+        return;
+    }
+
+    let settings = match SETTINGS.lock()
+        .expect("Failed to lock Markov settings")
+        .get()
+        .chat
+        .get(&message.channel_id.to_string())
+        .cloned() {
+        Some(settings) => settings,
+        None => return,
+    };
+
+    if rand::thread_rng().gen::<f64>() >= settings.probability {
+        return;
+    }
+
+    {
+        let mut cooldowns = CHAT_COOLDOWNS.lock().expect("Failed to lock chat cooldowns");
+        let cooldown = Duration::from_secs(CONFIG.markov.chat_cooldown);
+        if let Some(last) = cooldowns.get(&message.channel_id) {
+            if last.elapsed() < cooldown {
+                return;
+            }
+        }
+        cooldowns.insert(message.channel_id, Instant::now());
+    }
+
+    let name = settings.corpus;
     let channel_id = message.channel_id;
-    context.broadcast_typing(channel_id).map_err(stringify)?;
+    let guild_id = message.guild_id();
+    let line = sanitize(&message.content);
+    thread::spawn(move || {
+        let result = find_corpus(&name, guild_id).and_then(|config| {
+            with_corpus(&name, &config, |corpus| {
+                // Seed the reply with a random word from the message which the
+                // corpus knows, falling back to an unseeded reply.
+                let words = line.split_whitespace()
+                    .filter_map(|word| find_word(corpus, word))
+                    .collect::<Vec<&str>>();
+                let seeded = rand::thread_rng().choose(&words).and_then(|word| {
+                    let options = GenerateOptions {
+                        seed: Some(word.to_string()),
+                        ..GenerateOptions::default()
+                    };
+                    generate_text(corpus, &config, &options).ok()
+                });
+
+                match seeded {
+                    Some(text) => Ok(text),
+                    None => generate_text(corpus, &config, &GenerateOptions::default()),
+                }
+            })
+        });
+
+        match result {
+            Ok(Ok(text)) => check_msg(channel_id.say(&text)),
+            Ok(Err(err)) | Err(err) => warn!("Failed to generate chat reply: {}", err),
+        }
+    });
+}
+
+/// Trains the default chain in the background, so that the first use of the
+/// command does not have to wait for it.
+pub fn preload() {
+    thread::spawn(|| {
+        let name = &CONFIG.markov.default_corpus;
+        let result = find_corpus(name, None).and_then(|config| with_corpus(name, &config, |_| ()));
+        if let Err(err) = result {
+            warn!("Failed to preload Markov chain \"{}\": {}", name, err);
+        }
+    });
+}
+
+fn generate(
+    context: &Context,
+    message: &Message,
+    name: &str,
+    args: Vec<String>
+) -> Result<(), String> {
+    let config = find_corpus(name, message.guild_id())?;
+    generate_from(context, message, name, &config, args)
+}
+
+fn generate_from(
+    context: &Context,
+    message: &Message,
+    name: &str,
+    config: &CorpusConfig,
+    args: Vec<String>
+) -> Result<(), String> {
+    let options = GenerateOptions::parse(args)?;
+    let name = name.to_owned();
+    let config = config.clone();
+
+    job::run(context,
+             message,
+             "your text",
+             move || {
+                 with_corpus(&name, &config, |corpus| generate_text(corpus, &config, &options))?
+             },
+             |context, working, response| {
+                 let colour = random_colour();
+                 check_msg(context.edit_message(working.channel_id, working.id, "", |e| {
+                     e.colour(colour).description(response.as_ref())
+                 }));
+                 Ok(())
+             })
+}
+
+fn impersonate(
+    context: &Context,
+    message: &Message,
+    mention: &str,
+    args: Vec<String>
+) -> Result<(), String> {
+    let user_id = USER_MENTION_REGEX.captures(mention)
+        .and_then(|captures| captures.at(1))
+        .and_then(|id| id.parse::<u64>().ok())
+        .map(UserId)
+        .ok_or_else(|| "Please mention a valid user.".to_owned())?;
+
+    let guild_id = message.guild_id()
+        .ok_or_else(|| "Users can only be imitated in a server.".to_owned())?;
+
+    let consented = has_consented(SETTINGS.lock().expect("Failed to lock Markov settings").get(),
+                                  Some(guild_id),
+                                  user_id);
+    if !consented {
+        return Err("That user has not consented to being impersonated in this server.".to_owned());
+    }
+
+    let (name, config) = user_corpus(guild_id, user_id);
+    if !Path::new(&config.path).exists() {
+        return Err("No messages have been collected from that user yet.".to_owned());
+    }
+
+    generate_from(context, message, &name, &config, args)
+}
+
+fn set_consent(context: &Context, message: &Message, consent: bool) -> Result<(), String> {
+    let user_id = message.author.id;
+    let guild_id = message.guild_id()
+        .ok_or_else(|| "Consent can only be given or withdrawn in a server.".to_owned())?;
+    {
+        let mut settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        {
+            let users = settings.get_mut()
+                .guild_consents
+                .entry(guild_id.to_string())
+                .or_insert_with(HashSet::new);
+            if consent {
+                users.insert(user_id.0);
+            } else {
+                users.remove(&user_id.0);
+            }
+        }
+        settings.save();
+    }
+
+    let response = if consent {
+        "Your messages in this server's channels with learning enabled will now be collected, \
+         and others here will be able to imitate you. Use `fuyu forget` to withdraw your \
+         consent and delete the messages collected for imitating you."
+    } else {
+        forget_user(guild_id, user_id)?;
+        "The messages collected for imitating you in this server have been deleted, and you \
+         can no longer be imitated here. Messages already learned into this server's shared \
+         corpora are kept, as they are not recorded by author; use `markov optout` to stop \
+         your messages being learned at all."
+    };
+    check_msg(context.say(response));
+
+    Ok(())
+}
+
+// Checks whether a user has consented to being impersonated in a guild.
+fn has_consented(settings: &Settings, guild_id: Option<GuildId>, user_id: UserId) -> bool {
+    guild_id.and_then(|guild_id| settings.guild_consents.get(&guild_id.to_string()))
+        .map_or(false, |users| users.contains(&user_id.0))
+}
+
+// Deletes the corpus of a user in a guild, along with its trained and cached
+// chains.
+fn forget_user(guild_id: GuildId, user_id: UserId) -> Result<(), String> {
+    let (name, config) = user_corpus(guild_id, user_id);
+    let slot = corpus_slot(&name);
+    // Hold the corpus' lock while deleting, so that the learner thread does not
+    // write to the files in the meantime.
+    let mut corpus = slot.lock().expect("Failed to lock Markov chain");
+    *corpus = None;
+
+    let mut files = vec![PathBuf::from(&config.path)];
+    files.extend(cache_file(&name, &config));
+    for file in files {
+        match fs::remove_file(&file) {
+            Ok(()) => {},
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {},
+            Err(err) => return Err(format!("Failed to delete {}: {}", file.display(), err)),
+        }
+    }
+    info!("Deleted Markov data of user {} in guild {}", user_id.0, guild_id.0);
+
+    Ok(())
+}
+
+// Returns the name and config of the corpus of a user in a guild. Each guild
+// keeps its own corpus of a user, so that text is never carried between
+// guilds.
+fn user_corpus(guild_id: GuildId, user_id: UserId) -> (String, CorpusConfig) {
+    let config = CorpusConfig {
+        path: Path::new(&CONFIG.markov.user_corpus_dir)
+            .join(guild_id.to_string())
+            .join(format!("{}.txt", user_id.0))
+            .to_string_lossy()
+            .into_owned(),
+        guild: Some(guild_id.0),
+        order: USER_CORPUS_ORDER,
+        reject_verbatim: true,
+    };
+
+    (format!("user-{}-{}", guild_id.0, user_id.0), config)
+}
+
+fn list(context: &Context, message: &Message) -> Result<(), String> {
+    let guild_id = message.guild_id();
+    let mut names = CONFIG.markov
+        .corpora
+        .iter()
+        .filter(|&(_, config)| is_available(config, guild_id))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<String>>();
+    names.sort();
+
+    let response = if names.is_empty() {
+        "No corpora available.".to_owned()
+    } else {
+        format!("Available corpora: {}", names.join(", "))
+    };
+    check_msg(context.say(&response));
+
+    Ok(())
+}
+
+fn info(context: &Context, message: &Message, name: &str) -> Result<(), String> {
+    let config = find_corpus(name, message.guild_id())?;
+    let (lines, words, vocabulary) =
+        with_corpus(name, &config, |c| (c.lines, c.words, c.vocabulary.len()))?;
 
-    let response = create_chain().generate_str();
     let colour = random_colour();
-    check_msg(context.send_message(
-        channel_id,
-        |m| m.embed(|e| e.colour(colour).description(response.as_ref())),
-    ));
-});
+    check_msg(context.send_message(message.channel_id, |m| {
+        m.embed(|e| {
+            e.title(name)
+                .colour(colour)
+                .field(|f| f.name("Lines").value(&lines.to_string()))
+                .field(|f| f.name("Words").value(&words.to_string()))
+                .field(|f| f.name("Distinct words").value(&vocabulary.to_string()))
+                .field(|f| f.name("Order").value(&config.order.to_string()))
+                .footer(|f| {
+                    f.text(if config.guild.is_none() {
+                        "Generic"
+                    } else {
+                        "Server-specific"
+                    })
+                })
+        })
+    }));
+
+    Ok(())
+}
 
-fn create_chain() -> Chain<String> {
-    let chat_logs = load_chat_logs();
-    let mut chain = Chain::new();
-    for line in chat_logs.split("\n\n") {
-        chain.feed_str(line);
+fn learn(context: &Context, message: &Message, name: Option<String>) -> Result<(), String> {
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Err("Learning can only be enabled in a server.".to_owned()),
+    };
+
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
     }
-    chain
+
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return Err("Please specify a corpus to learn into, or \"off\" to disable learning."
+                .to_owned())
+        },
+    };
+
+    let channel = message.channel_id.to_string();
+    let response = if name == "off" {
+        let mut settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        settings.get_mut().learning.remove(&channel);
+        settings.save();
+
+        "Learning disabled in this channel.".to_owned()
+    } else {
+        // Only corpora owned by the guild may be learned into, so that one
+        // guild cannot affect the output of corpora shared with others.
+        let config = find_corpus(&name, Some(guild_id))?;
+        if config.guild != Some(guild_id.0) {
+            return Err(format!("Corpus \"{}\" is not owned by this server.", name));
+        }
+
+        let mut settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        settings.get_mut().learning.insert(channel, name.clone());
+        settings.save();
+
+        format!("Messages in this channel will now be learned into \"{}\".", name)
+    };
+    check_msg(context.say(&response));
+
+    Ok(())
 }
 
-#[cfg(feature = "fuyu-include")]
-fn load_chat_logs() -> String {
-    const FUYU_CHAT_LOGS: &'static str = include_str!("../../logs/fuyu.txt");
+fn chat(context: &Context, message: &Message, args: Vec<String>) -> Result<(), String> {
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Err("Chat mode can only be enabled in a server.".to_owned()),
+    };
+
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
 
-    FUYU_CHAT_LOGS.to_owned()
+    let mut args = args.into_iter();
+    let name = match args.next() {
+        Some(name) => name,
+        None => {
+            return Err("Please specify a corpus to reply from, or \"off\" to disable chat mode."
+                .to_owned())
+        },
+    };
+
+    let channel = message.channel_id.to_string();
+    let response = if name == "off" {
+        let mut settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        settings.get_mut().chat.remove(&channel);
+        settings.save();
+
+        "Chat mode disabled in this channel.".to_owned()
+    } else {
+        find_corpus(&name, Some(guild_id))?;
+        let probability = match args.next().map(|p| p.parse::<f64>()) {
+            Some(Ok(probability)) if probability > 0.0 && probability <= 1.0 => probability,
+            Some(_) => return Err("The reply probability must be between 0 and 1.".to_owned()),
+            None => 1.0,
+        };
+
+        let mut settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        settings.get_mut().chat.insert(channel,
+                                       ChatSettings {
+                                           corpus: name.clone(),
+                                           probability: probability,
+                                       });
+        settings.save();
+
+        format!("Chat mode enabled in this channel, replying from \"{}\" with probability {}.",
+                name,
+                probability)
+    };
+    check_msg(context.say(&response));
+
+    Ok(())
+}
+
+fn set_optout(context: &Context, message: &Message, optout: bool) -> Result<(), String> {
+    let mut settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+    let response = if optout {
+        settings.get_mut().optouts.insert(message.author.id.0);
+        "Your messages will no longer be learned from."
+    } else {
+        settings.get_mut().optouts.remove(&message.author.id.0);
+        "Your messages may now be learned from."
+    };
+    settings.save();
+    check_msg(context.say(response));
+
+    Ok(())
+}
+
+// Removes mentions from a message, and collapses its whitespace.
+fn sanitize(content: &str) -> String {
+    normalize(&MENTION_REGEX.replace_all(content, ""))
+}
+
+// Appends a line to a corpus file, and trains the corpus' chain on it if the
+// chain has already been loaded.
+fn learn_line(lesson: &Lesson) -> Result<(), String> {
+    let (name, config, line) = (&lesson.name, &lesson.config, &lesson.line);
+    // Hold the corpus' lock while writing, so that the chain is not retrained
+    // from a partially written file.
+    let slot = corpus_slot(name);
+    let mut corpus = slot.lock().expect("Failed to lock Markov chain");
+
+    if let Some((guild_id, user_id)) = lesson.owner {
+        let settings = SETTINGS.lock().expect("Failed to lock Markov settings");
+        if !has_consented(settings.get(), Some(guild_id), user_id) {
+            return Ok(());
+        }
+    }
+
+    if let Some(dir) = Path::new(&config.path).parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("Failed to create corpus directory: {}", err))?;
+    }
+    let mut file = OpenOptions::new().append(true)
+        .create(true)
+        .open(&config.path)
+        .map_err(|err| format!("Failed to open corpus file: {}", err))?;
+    file.write_all(format!("\n\n{}", line).as_bytes())
+        .map_err(|err| format!("Failed to write to corpus file: {}", err))?;
+
+    if let Some(ref mut corpus) = *corpus {
+        corpus.feed(line);
+        corpus.modified = corpus_modified(&config.path);
+    }
+    trace!("Learned line into corpus \"{}\"", name);
+
+    Ok(())
+}
+
+// Returns the config for the named corpus, if it exists and is available in
+// the specified guild.
+fn find_corpus(name: &str, guild_id: Option<GuildId>) -> Result<CorpusConfig, String> {
+    CONFIG.markov
+        .corpora
+        .get(name)
+        .and_then(|config| if is_available(config, guild_id) {
+            Some(config.clone())
+        } else {
+            None
+        })
+        .ok_or_else(|| format!("Corpus \"{}\" not found", name))
+}
+
+fn is_available(config: &CorpusConfig, guild_id: Option<GuildId>) -> bool {
+    match config.guild {
+        Some(owner) => guild_id.map(|g| g.0) == Some(owner),
+        None => true,
+    }
+}
+
+// Generates text from a corpus, retrying until the text satisfies the options
+// and passes the output filters.
+fn generate_text(
+    corpus: &Corpus,
+    config: &CorpusConfig,
+    options: &GenerateOptions
+) -> Result<String, String> {
+    const MAX_ATTEMPTS: usize = 50;
+
+    let seed = match options.seed {
+        Some(ref word) => {
+            match find_word(corpus, word) {
+                Some(word) => Some(word),
+                None => return Err(format!("The word \"{}\" does not appear in the corpus.", word)),
+            }
+        },
+        None => None,
+    };
+
+    for _ in 0..MAX_ATTEMPTS {
+        let text = match seed {
+            Some(word) => corpus.chain.generate_str_from_token(word),
+            None => corpus.chain.generate_str(),
+        };
+
+        let words = text.split_whitespace().count();
+        if words == 0 || options.min_words.map_or(false, |min| words < min) ||
+           options.max_words.map_or(false, |max| words > max) {
+            continue;
+        }
+
+        if config.reject_verbatim && corpus.sources.contains(&normalize(&text)) {
+            trace!("Rejecting generated text which reproduces the corpus");
+            continue;
+        }
+
+        if is_blocked(&text, BLOCKLIST_REGEX.as_ref()) {
+            trace!("Rejecting generated text containing blocked words");
+            continue;
+        }
+
+        return Ok(text);
+    }
+
+    Err(format!("Failed to generate a suitable message after {} attempts.", MAX_ATTEMPTS))
+}
+
+// Checks whether text contains mentions or any of the blocked words.
+fn is_blocked(text: &str, blocklist: Option<&Regex>) -> bool {
+    MENTION_REGEX.is_match(text) || blocklist.map_or(false, |regex| regex.is_match(text))
+}
+
+// Builds a case-insensitive regex matching any of the blocked words where they
+// appear as whole words, so that e.g. blocking "ass" does not block "class".
+fn blocklist_regex(blocklist: &[String]) -> Option<Regex> {
+    let words = blocklist.iter()
+        .map(|word| word.trim())
+        .filter(|word| !word.is_empty())
+        .map(regex::quote)
+        .collect::<Vec<String>>();
+    if words.is_empty() {
+        return None;
+    }
+
+    let pattern = format!(r"(?i)(?:^|\W)(?:{})(?:\W|$)", words.join("|"));
+    Some(Regex::new(&pattern).expect("Failed to compile blocklist"))
+}
+
+// Collapses the whitespace in a line, so that lines can be compared.
+fn normalize(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Finds a word in the corpus' vocabulary, preferring an exact match but
+// falling back to a case-insensitive one.
+fn find_word<'a>(corpus: &'a Corpus, word: &str) -> Option<&'a str> {
+    if let Some(word) = corpus.vocabulary.get(word) {
+        return Some(word.as_str());
+    }
+
+    let lowercase = word.to_lowercase();
+    corpus.vocabulary
+        .iter()
+        .find(|w| w.to_lowercase() == lowercase)
+        .map(String::as_str)
+}
+
+// Calls `f` with the trained corpus, training it first if it has not been
+// trained yet or if the corpus file has changed since it was trained.
+fn with_corpus<F, T>(name: &str, config: &CorpusConfig, f: F) -> Result<T, String>
+    where F: FnOnce(&Corpus) -> T,
+{
+    // Only the corpus' own lock is held while training, so that other corpora
+    // remain usable in the meantime.
+    let slot = corpus_slot(name);
+    let mut corpus = slot.lock().expect("Failed to lock Markov chain");
+    let modified = corpus_modified(&config.path);
+
+    let stale = match *corpus {
+        Some(ref corpus) => corpus.modified != modified || corpus.order != config.order,
+        None => true,
+    };
+    if stale {
+        let mut trained = load_corpus(name, config, modified)?;
+        trained.modified = modified;
+        *corpus = Some(trained);
+    }
+
+    Ok(f(corpus.as_ref().expect("Corpus was just trained")))
+}
+
+// Returns the slot holding the trained chain of the named corpus, which is
+// empty until the chain is first used.
+fn corpus_slot(name: &str) -> Arc<Mutex<Option<Corpus>>> {
+    let mut chains = CHAINS.lock().expect("Failed to lock Markov chains");
+    chains.entry(name.to_owned()).or_insert_with(|| Arc::new(Mutex::new(None))).clone()
+}
+
+// Trains the corpus from its file, loading the chain itself from the cache if
+// the cache is up to date with the file, and otherwise updating the cache.
+fn load_corpus(
+    name: &str,
+    config: &CorpusConfig,
+    modified: Option<SystemTime>
+) -> Result<Corpus, String> {
+    let contents = load_corpus_file(&config.path)?;
+    let lines = contents.split("\n\n")
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>();
+
+    if config.order == 0 {
+        return Err(format!("Corpus \"{}\" has an invalid order of 0", name));
+    }
+
+    let mut corpus = Corpus::new(config.order);
+    for line in &lines {
+        corpus.count(line);
+    }
+
+    // Only chains trained from corpora on disk are cached.
+    let cache_path = match modified {
+        Some(_) => cache_file(name, config),
+        None => None,
+    };
+
+    if let Some(ref cache_path) = cache_path {
+        let cache_modified = fs::metadata(cache_path).and_then(|m| m.modified()).ok();
+        if cache_modified.is_some() && cache_modified >= modified {
+            match Chain::load(cache_path) {
+                Ok(chain) => {
+                    debug!("Loaded Markov chain from cache: {}", cache_path.display());
+                    corpus.chain = chain;
+                    return Ok(corpus);
+                },
+                Err(err) => warn!("Failed to load cached Markov chain: {}", err),
+            }
+        }
+    }
+
+    for line in &lines {
+        corpus.chain.feed_str(line);
+    }
+    debug!("Trained Markov chain \"{}\"", name);
+
+    if let Some(ref cache_path) = cache_path {
+        if let Err(err) = corpus.chain.save(cache_path) {
+            warn!("Failed to cache Markov chain: {}", err);
+        }
+    }
+    Ok(corpus)
 }
 
-#[cfg(not(feature = "fuyu-include"))]
-fn load_chat_logs() -> String {
-    use std::fs::File;
-    use std::io::Read;
+// Returns the path at which the chain of a corpus is cached, if caching is
+// enabled.
+fn cache_file(name: &str, config: &CorpusConfig) -> Option<PathBuf> {
+    CONFIG.markov
+        .cache_dir
+        .as_ref()
+        .map(|dir| Path::new(dir).join(format!("{}.{}.chain", name, config.order)))
+}
 
-    const FILE_NAME: &'static str = "logs/fuyu.txt";
+fn load_corpus_file(path: &str) -> Result<String, String> {
+    #[cfg(feature = "fuyu-include")]
+    {
+        if path == INCLUDED_CORPUS_PATH {
+            return Ok(INCLUDED_CORPUS.to_owned());
+        }
+    }
 
-    let mut file = File::open(FILE_NAME).expect("Failed to open chat log file");
+    let mut file = File::open(path).map_err(|err| format!("Failed to open corpus file: {}", err))?;
     let mut contents = String::new();
-    file.read_to_string(&mut contents).expect("Failed to read chat log file");
+    file.read_to_string(&mut contents)
+        .map_err(|err| format!("Failed to read corpus file: {}", err))?;
+
+    Ok(contents)
+}
+
+fn corpus_modified(path: &str) -> Option<SystemTime> {
+    // The included corpus never changes, so it has no modification time.
+    #[cfg(feature = "fuyu-include")]
+    {
+        if path == INCLUDED_CORPUS_PATH {
+            return None;
+        }
+    }
+
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(feature = "fuyu-include")]
+const INCLUDED_CORPUS_PATH: &'static str = "logs/fuyu.txt";
+
+#[cfg(feature = "fuyu-include")]
+const INCLUDED_CORPUS: &'static str = include_str!("../../logs/fuyu.txt");
+
+#[cfg(test)]
+mod tests {
+    use config::CorpusConfig;
+    use serenity::model::{GuildId, UserId};
+    use std::collections::HashSet;
+    use super::{Corpus, GenerateOptions, Settings, blocklist_regex, generate_text, has_consented,
+                is_blocked, sanitize, user_corpus};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn corpus(lines: &[&str]) -> Corpus {
+        let mut corpus = Corpus::new(1);
+        for line in lines {
+            corpus.feed(line);
+        }
+        corpus
+    }
+
+    fn corpus_config(reject_verbatim: bool) -> CorpusConfig {
+        CorpusConfig {
+            path: "fuyu.txt".to_owned(),
+            guild: None,
+            order: 1,
+            reject_verbatim: reject_verbatim,
+        }
+    }
+
+    #[test]
+    fn test_corpus_statistics() {
+        let mut corpus = Corpus::new(1);
+        corpus.feed("the quick  fox");
+        corpus.count("the lazy dog");
+
+        assert_eq!(corpus.lines, 2);
+        assert_eq!(corpus.words, 6);
+        assert_eq!(corpus.vocabulary.len(), 5);
+        assert!(corpus.sources.contains("the quick fox"));
+        assert!(corpus.sources.contains("the lazy dog"));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("hi <@1234> and <@!5678>"), "hi and");
+        assert_eq!(sanitize("ping <@&42> in <#99>"), "ping in");
+        assert_eq!(sanitize("  @everyone   look\n@here  "), "look");
+        assert_eq!(sanitize("plain  text"), "plain text");
+    }
+
+    #[test]
+    fn test_parse_generate_options() {
+        let options = GenerateOptions::parse(args(&["about", "cats", "--min", "3", "--max", "8"]))
+            .unwrap();
+        assert_eq!(options.seed, Some("cats".to_owned()));
+        assert_eq!(options.min_words, Some(3));
+        assert_eq!(options.max_words, Some(8));
+
+        let options = GenerateOptions::parse(Vec::new()).unwrap();
+        assert_eq!(options.seed, None);
+        assert_eq!(options.min_words, None);
+        assert_eq!(options.max_words, None);
+    }
+
+    #[test]
+    fn test_parse_generate_options_errors() {
+        assert_eq!(GenerateOptions::parse(args(&["--min", "5", "--max", "2"])).unwrap_err(),
+                   "The minimum word count cannot exceed the maximum.");
+        assert_eq!(GenerateOptions::parse(args(&["about"])).unwrap_err(),
+                   "Please specify a word to generate text about.");
+        assert_eq!(GenerateOptions::parse(args(&["--min", "0"])).unwrap_err(),
+                   "Please specify a valid word count.");
+        assert_eq!(GenerateOptions::parse(args(&["--max"])).unwrap_err(),
+                   "Please specify a valid word count.");
+        assert_eq!(GenerateOptions::parse(args(&["loudly"])).unwrap_err(),
+                   "Unknown option: loudly");
+    }
+
+    #[test]
+    fn test_generate_text_from_seed() {
+        let corpus = corpus(&["the cat sat", "a dog ran"]);
+        let options = GenerateOptions { seed: Some("DOG".to_owned()), ..Default::default() };
+
+        assert_eq!(generate_text(&corpus, &corpus_config(false), &options).unwrap(),
+                   "dog ran");
+    }
+
+    #[test]
+    fn test_generate_text_rejects_unknown_seed() {
+        let corpus = corpus(&["the cat sat"]);
+        let options = GenerateOptions { seed: Some("dog".to_owned()), ..Default::default() };
+
+        assert_eq!(generate_text(&corpus, &corpus_config(false), &options).unwrap_err(),
+                   "The word \"dog\" does not appear in the corpus.");
+    }
+
+    #[test]
+    fn test_generate_text_rejects_verbatim_lines() {
+        let corpus = corpus(&["hello world"]);
+        let options = GenerateOptions::default();
 
-    contents
+        assert_eq!(generate_text(&corpus, &corpus_config(false), &options).unwrap(),
+                   "hello world");
+        assert_eq!(generate_text(&corpus, &corpus_config(true), &options).unwrap_err(),
+                   "Failed to generate a suitable message after 50 attempts.");
+    }
+
+    #[test]
+    fn test_is_blocked() {
+        let blocklist = blocklist_regex(&args(&["ass", "bad word"]));
+        let blocklist = blocklist.as_ref();
+
+        assert!(is_blocked("you ASS", blocklist));
+        assert!(is_blocked("ass, again", blocklist));
+        assert!(is_blocked("such a bad word!", blocklist));
+        assert!(!is_blocked("a class act", blocklist));
+        assert!(!is_blocked("bad words", blocklist));
+        assert!(!is_blocked("nothing to see", blocklist));
+    }
+
+    #[test]
+    fn test_is_blocked_mentions() {
+        assert!(is_blocked("hi <@1234>", None));
+        assert!(is_blocked("@everyone look", None));
+        assert!(is_blocked("look @here", None));
+        assert!(!is_blocked("an email@example.com", None));
+    }
+
+    #[test]
+    fn test_blocklist_regex_without_words() {
+        assert!(blocklist_regex(&[]).is_none());
+        assert!(blocklist_regex(&args(&["", "  "])).is_none());
+    }
+
+    #[test]
+    fn test_has_consented() {
+        let mut settings = Settings::default();
+        let mut users = HashSet::new();
+        users.insert(42);
+        settings.guild_consents.insert("1".to_owned(), users);
+
+        assert!(has_consented(&settings, Some(GuildId(1)), UserId(42)));
+        assert!(!has_consented(&settings, Some(GuildId(1)), UserId(43)));
+        assert!(!has_consented(&settings, Some(GuildId(2)), UserId(42)));
+        assert!(!has_consented(&settings, None, UserId(42)));
+    }
+
+    #[test]
+    fn test_user_corpus_is_per_guild() {
+        let (name, config) = user_corpus(GuildId(1), UserId(42));
+        let (other_name, other_config) = user_corpus(GuildId(2), UserId(42));
+
+        assert_eq!(name, "user-1-42");
+        assert_eq!(config.guild, Some(1));
+        assert!(name != other_name);
+        assert!(config.path != other_config.path);
+    }
 }
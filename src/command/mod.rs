@@ -7,10 +7,16 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+#[cfg(feature = "calc")]
+pub mod calc;
+#[cfg(feature = "fuyu")]
+pub mod chain;
 #[cfg(feature = "fuyu")]
 pub mod fuyu;
 #[cfg(feature = "ping")]
 pub mod ping;
+#[cfg(any(feature = "wolfram", feature = "xkcd"))]
+pub mod quota;
 #[cfg(feature = "roll")]
 pub mod roll;
 #[cfg(feature = "stats")]
@@ -21,3 +27,7 @@ pub mod tag;
 pub mod wolfram_alpha;
 #[cfg(feature = "xkcd")]
 pub mod xkcd;
+#[cfg(feature = "xkcd")]
+pub mod xkcd_index;
+#[cfg(feature = "xkcd")]
+pub mod xkcd_search;
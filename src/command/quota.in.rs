@@ -0,0 +1,8 @@
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Usage {
+    // The UTC date to which the counts apply, as `YYYY-MM-DD`.
+    day: String,
+    // The number of calls made today, keyed by service and caller, e.g.
+    // `wolfram:user:1234`.
+    counts: HashMap<String, u64>,
+}
@@ -0,0 +1,271 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides daily quotas on calls to paid external APIs, and a command which
+//! allows a user to view their remaining usage.
+//!
+//! Calls are counted per user and per guild, and the counts reset at midnight
+//! UTC. Responses served from a cache do not count against the quota.
+
+use ::CONFIG;
+use chrono::UTC;
+use config::QuotaLimits;
+use serenity::model::{GuildId, Message, UserId};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use store::Store;
+use util::check_msg;
+
+lazy_static! {
+    static ref USAGE: Mutex<Store<Usage>> = Mutex::new(Store::new("quota.json"));
+}
+
+#[cfg(feature = "nightly")]
+include!("quota.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/quota.rs"));
+
+/// An external API whose calls are subject to a quota.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Service {
+    /// The Wolfram|Alpha APIs.
+    Wolfram,
+    /// The Google Custom Search API, used to search for xkcd comics.
+    GoogleCse,
+}
+
+impl Service {
+    fn name(&self) -> &'static str {
+        match *self {
+            Service::Wolfram => "Wolfram|Alpha",
+            Service::GoogleCse => "xkcd search",
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match *self {
+            Service::Wolfram => "wolfram",
+            Service::GoogleCse => "google_cse",
+        }
+    }
+
+    fn limits(&self) -> &'static QuotaLimits {
+        match *self {
+            Service::Wolfram => &CONFIG.quota.wolfram,
+            Service::GoogleCse => &CONFIG.quota.google_cse,
+        }
+    }
+}
+
+/// The user on whose behalf an external API call is made.
+#[derive(Clone, Copy, Debug)]
+pub struct Caller {
+    /// The user who ran the command.
+    pub user: UserId,
+    /// The guild in which the command was run, if any.
+    pub guild: Option<GuildId>,
+}
+
+impl Caller {
+    /// Returns the `Caller` for the author of a message.
+    pub fn new(message: &Message) -> Self {
+        Caller {
+            user: message.author.id,
+            guild: message.guild_id(),
+        }
+    }
+
+    fn user_key(&self, service: Service) -> String {
+        format!("{}:user:{}", service.key(), self.user.0)
+    }
+
+    fn guild_key(&self, service: Service) -> Option<String> {
+        self.guild.map(|guild| format!("{}:guild:{}", service.key(), guild.0))
+    }
+}
+
+/// Records a call to an external API, or returns a message explaining why the
+/// call may not be made if the caller has exceeded their quota.
+///
+/// Bot owners are not subject to quotas, although their calls are counted
+/// against their guild's quota.
+pub fn consume(service: Service, caller: &Caller) -> Result<(), String> {
+    let mut usage = USAGE.lock().expect("Failed to lock quota usage");
+    reset_if_stale(usage.get_mut(), &today());
+    let exempt = CONFIG.owners.contains(&caller.user.0);
+    record(usage.get_mut(), service, caller, service.limits(), exempt)?;
+    usage.save();
+
+    Ok(())
+}
+
+// Counts a call against the quotas of a caller and their guild, unless either
+// has been exceeded. Exempt callers are only subject to their guild's quota.
+fn record(
+    usage: &mut Usage,
+    service: Service,
+    caller: &Caller,
+    limits: &QuotaLimits,
+    exempt: bool
+) -> Result<(), String> {
+    {
+        let counts = &usage.counts;
+        let used = |key: &str| counts.get(key).cloned().unwrap_or(0);
+
+        if !exempt &&
+           limits.per_user.map_or(false, |limit| used(&caller.user_key(service)) >= limit) {
+            return Err(format!("You have used up your {} quota for today. It resets at midnight \
+                                UTC.",
+                               service.name()));
+        }
+        if let Some(key) = caller.guild_key(service) {
+            if limits.per_guild.map_or(false, |limit| used(&key) >= limit) {
+                return Err(format!("This server has used up its {} quota for today. It resets \
+                                    at midnight UTC.",
+                                   service.name()));
+            }
+        }
+    }
+
+    let counts = &mut usage.counts;
+    *counts.entry(caller.user_key(service)).or_insert(0) += 1;
+    if let Some(key) = caller.guild_key(service) {
+        *counts.entry(key).or_insert(0) += 1;
+    }
+
+    Ok(())
+}
+
+// Returns the current UTC date, as `YYYY-MM-DD`.
+fn today() -> String {
+    UTC::today().format("%Y-%m-%d").to_string()
+}
+
+// Clears the counts if they were made before the specified day.
+fn reset_if_stale(usage: &mut Usage, today: &str) {
+    if usage.day != today {
+        usage.day = today.to_owned();
+        usage.counts.clear();
+    }
+}
+
+command!(quota(context, message, _args) {
+    let caller = Caller::new(message);
+    let mut usage = USAGE.lock().expect("Failed to lock quota usage");
+    reset_if_stale(usage.get_mut(), &today());
+    let counts = &usage.get().counts;
+    let used = |key: &str| counts.get(key).cloned().unwrap_or(0);
+    let remaining = |used: u64, limit: Option<u64>| match limit {
+        Some(limit) => format!("{}/{} left", limit.saturating_sub(used), limit),
+        None => "unlimited".to_owned(),
+    };
+
+    let mut response = String::from("Remaining usage for today (quotas reset at midnight UTC):\n");
+    for service in services() {
+        let limits = service.limits();
+        response.push_str(&format!("**{}**: {} for you",
+                                   service.name(),
+                                   remaining(used(&caller.user_key(service)), limits.per_user)));
+        if let Some(key) = caller.guild_key(service) {
+            response.push_str(&format!(", {} for this server",
+                                       remaining(used(&key), limits.per_guild)));
+        }
+        response.push('\n');
+    }
+
+    check_msg(context.say(&response));
+});
+
+// Returns the services used by the enabled commands.
+fn services() -> Vec<Service> {
+    let mut services = Vec::new();
+    #[cfg(feature = "wolfram")]
+    {
+        services.push(Service::Wolfram);
+    }
+    #[cfg(feature = "xkcd")]
+    {
+        // Only some search providers have a quota.
+        services.extend(::command::xkcd::search_quota());
+    }
+    services
+}
+
+#[cfg(test)]
+mod tests {
+    use config::QuotaLimits;
+    use serenity::model::{GuildId, UserId};
+    use super::{Caller, Service, Usage, record, reset_if_stale};
+
+    fn caller(user: u64, guild: Option<u64>) -> Caller {
+        Caller {
+            user: UserId(user),
+            guild: guild.map(GuildId),
+        }
+    }
+
+    fn limits(per_user: Option<u64>, per_guild: Option<u64>) -> QuotaLimits {
+        QuotaLimits {
+            per_user: per_user,
+            per_guild: per_guild,
+        }
+    }
+
+    #[test]
+    fn test_record_user_limit() {
+        let mut usage = Usage::default();
+        let limits = limits(Some(2), None);
+        let alice = caller(1, None);
+
+        assert!(record(&mut usage, Service::Wolfram, &alice, &limits, false).is_ok());
+        assert!(record(&mut usage, Service::Wolfram, &alice, &limits, false).is_ok());
+        assert_eq!(record(&mut usage, Service::Wolfram, &alice, &limits, false).unwrap_err(),
+                   "You have used up your Wolfram|Alpha quota for today. It resets at midnight \
+                    UTC.");
+        assert_eq!(usage.counts.get("wolfram:user:1"), Some(&2));
+
+        // Other users and services are counted separately.
+        assert!(record(&mut usage, Service::Wolfram, &caller(2, None), &limits, false).is_ok());
+        assert!(record(&mut usage, Service::GoogleCse, &alice, &limits, false).is_ok());
+        // Exempt users are not subject to their own quota.
+        assert!(record(&mut usage, Service::Wolfram, &alice, &limits, true).is_ok());
+    }
+
+    #[test]
+    fn test_record_guild_limit() {
+        let mut usage = Usage::default();
+        let limits = limits(None, Some(2));
+
+        assert!(record(&mut usage, Service::Wolfram, &caller(1, Some(9)), &limits, false).is_ok());
+        assert!(record(&mut usage, Service::Wolfram, &caller(2, Some(9)), &limits, true).is_ok());
+        assert_eq!(record(&mut usage, Service::Wolfram, &caller(3, Some(9)), &limits, true)
+                       .unwrap_err(),
+                   "This server has used up its Wolfram|Alpha quota for today. It resets at \
+                    midnight UTC.");
+        assert!(record(&mut usage, Service::Wolfram, &caller(3, Some(8)), &limits, false).is_ok());
+        assert!(record(&mut usage, Service::Wolfram, &caller(3, None), &limits, false).is_ok());
+        assert_eq!(usage.counts.get("wolfram:guild:9"), Some(&2));
+    }
+
+    #[test]
+    fn test_reset_if_stale() {
+        let mut usage = Usage::default();
+        reset_if_stale(&mut usage, "2016-11-05");
+        record(&mut usage, Service::Wolfram, &caller(1, Some(9)), &limits(None, None), false)
+            .unwrap();
+
+        reset_if_stale(&mut usage, "2016-11-05");
+        assert_eq!(usage.counts.len(), 2);
+
+        reset_if_stale(&mut usage, "2016-11-06");
+        assert_eq!(usage.day, "2016-11-06");
+        assert!(usage.counts.is_empty());
+    }
+}
@@ -13,6 +13,7 @@ use ::{CONFIG, UPTIME};
 use chrono::UTC;
 use serenity::client::CACHE;
 use serenity::model::{Guild, GuildChannel, UserId};
+use serenity::utils::builder::CreateEmbed;
 use util::{check_msg, duration_to_string, timestamp_to_string};
 
 const BYTES_TO_MEGABYTES: f64 = 1f64 / (1024f64 * 1024f64);
@@ -66,7 +67,7 @@ command!(stats(context, message, _args) {
 
     check_msg(context.send_message(message.channel_id, |m| {
         m.embed(|e| {
-            e.title(&format!("{} stats", CONFIG.bot_name))
+            let e = e.title(&format!("{} stats", CONFIG.bot_name))
                 .field(|f| f.name("Members").value(&users_count.to_string()))
                 .field(|f| f.name("Channels").value(&channels_count.to_string()))
                 .field(|f| f.name("Uptime").value(&duration_to_string(&uptime)))
@@ -80,11 +81,29 @@ command!(stats(context, message, _args) {
                                         round(shared_mem, 2)))
                 })
                 .field(|f| f.name("Source").value(&CONFIG.source_url))
-                .timestamp(timestamp_to_string(&current_time))
+                .timestamp(timestamp_to_string(&current_time));
+            plugin_stats(e)
         })
     }));
 });
 
+/// Adds statistics provided by enabled plugins to the stats embed.
+#[allow(unused_mut)]
+fn plugin_stats(mut embed: CreateEmbed) -> CreateEmbed {
+    #[cfg(feature = "wolfram")]
+    {
+        use command::wolfram_alpha;
+
+        let (hits, misses) = wolfram_alpha::cache_stats();
+        embed = embed.field(|f| {
+            f.name("Wolfram|Alpha Cache")
+                .value(&format!("Hits: {}\nMisses: {}", hits, misses))
+        });
+    }
+
+    embed
+}
+
 /// Rounds a number to the specified decimal precision.
 #[inline]
 fn round(num: f64, precision: i32) -> f64 {
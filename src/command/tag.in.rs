@@ -7,3 +7,20 @@ struct Tag {
     location: Option<String>,
     created_at: DateTime<UTC>,
 }
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct GuildSettings {
+    max_tags_per_user: Option<usize>,
+    max_tags_per_guild: Option<usize>,
+    max_content_length: Option<usize>,
+    #[serde(default)]
+    shortcut_prefix: Option<String>,
+    #[serde(default)]
+    autoresponders: Vec<Autoresponder>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Autoresponder {
+    pattern: String,
+    tag: String,
+}
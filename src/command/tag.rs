@@ -9,26 +9,39 @@
 
 //! Provides functionality for the `tag` command.
 
+extern crate regex;
 extern crate uuid;
 
+use ::CONFIG;
 use chrono::{DateTime, UTC};
+use config::TagLimits;
+use self::regex::Regex;
 use self::uuid::Uuid;
 use serde_json;
 use serenity::client::{Context, rest};
-use serenity::model::{GuildId, Message, UserId};
+use serenity::model::{ChannelId, GuildId, Message, UserId};
 use serenity::utils::builder::CreateEmbed;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{ErrorKind, Read, Write};
-use std::sync::Mutex;
-use util::{check_msg, merge, timestamp_to_string};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use store::Store;
+use util::{check_msg, is_admin, merge, timestamp_to_string};
 
 lazy_static! {
     static ref TAGS: Tags = Tags {
         config: Mutex::new(Config::new("tags.json")),
     };
+    static ref SETTINGS: Mutex<Store<HashMap<String, GuildSettings>>> =
+        Mutex::new(Store::new("tag_settings.json"));
+    static ref COOLDOWNS: Mutex<HashMap<ChannelId, Instant>> = Mutex::new(HashMap::new());
+    // The compiled autoresponder patterns, or `None` for invalid patterns.
+    static ref PATTERNS: Mutex<HashMap<String, Option<Regex>>> = Mutex::new(HashMap::new());
 }
 
+const DEFAULT_SHORTCUT_PREFIX: &'static str = "!!";
+
 #[cfg(feature = "nightly")]
 include!("tag.in.rs");
 
@@ -94,6 +107,9 @@ impl Tag {
 struct Config {
     name: String,
     tags: HashMap<String, HashMap<String, Tag>>,
+    // The modification time and length of the file as of the last load or
+    // save, used to detect changes made to the file by other programs.
+    version: Option<(SystemTime, u64)>,
 }
 
 impl Config {
@@ -101,9 +117,10 @@ impl Config {
         let mut config = Config {
             name: name.to_owned(),
             tags: HashMap::new(),
+            version: None,
         };
 
-        config.load();
+        config.load().expect("Failed to load Config");
 
         config
     }
@@ -112,27 +129,66 @@ impl Config {
         self.tags.get(key)
     }
 
-    fn insert(&mut self, key: String, value: HashMap<String, Tag>) {
+    fn insert(&mut self, key: String, value: HashMap<String, Tag>) -> Result<(), String> {
         self.tags.insert(key, value);
-        self.save();
+        self.save()
+    }
+
+    // Returns the modification time and length of the file on disk, if it
+    // exists.
+    fn disk_version(&self) -> Option<(SystemTime, u64)> {
+        fs::metadata(&self.name)
+            .and_then(|m| m.modified().map(|modified| (modified, m.len())))
+            .ok()
+    }
+
+    // Reloads the config if the file has been changed since it was last loaded
+    // or saved.
+    fn refresh(&mut self) -> Result<(), String> {
+        if self.disk_version() == self.version {
+            return Ok(());
+        }
+
+        info!("Detected external changes to {}, reloading", self.name);
+        self.load()
     }
 
-    fn load(&mut self) {
+    fn load(&mut self) -> Result<(), String> {
+        let version = self.disk_version();
         let mut file = match File::open(&self.name) {
             Ok(file) => file,
             // If no file is present, assume this is a fresh config.
-            Err(ref err) if err.kind() == ErrorKind::NotFound => return,
-            Err(_) => panic!("Failed to open file: {}", self.name),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                self.version = None;
+                return Ok(());
+            },
+            Err(err) => return Err(format!("Failed to open file {}: {}", self.name, err)),
         };
         let mut tags = String::new();
         file.read_to_string(&mut tags)
-            .expect(&format!("Failed to read from file: {}", self.name));
-        self.tags = serde_json::from_str(&tags).expect("Failed to deserialize Config");
+            .map_err(|err| format!("Failed to read from file {}: {}", self.name, err))?;
+        // If the file cannot be parsed, the in-memory tags are kept, and the
+        // file is left untouched until it is fixed.
+        self.tags = serde_json::from_str(&tags)
+            .map_err(|err| format!("Failed to deserialize {}: {}", self.name, err))?;
+        self.version = version;
         debug!("Loaded config from: {}", self.name);
+
+        Ok(())
     }
 
-    fn save(&self) {
-        let temp = format!("{}-{}.tmp", Uuid::new_v4(), self.name);
+    fn save(&mut self) -> Result<(), String> {
+        // Never overwrite changes which were made to the file after it was last
+        // loaded; reload them instead, and let the caller retry.
+        if self.disk_version() != self.version {
+            warn!("{} was modified externally, refusing to overwrite it", self.name);
+            self.load()?;
+            return Err("The tag database was modified externally and has been reloaded. \
+                        Please try again."
+                .to_owned());
+        }
+
+        let temp = format!("{}.{}.tmp", self.name, Uuid::new_v4());
         let mut file = File::create(&temp).expect(&format!("Failed to create file: {}", temp));
         file.write_all(serde_json::to_string(&self.tags)
                 .expect("Failed to serialize Config")
@@ -141,7 +197,10 @@ impl Config {
 
         // Atomically copy the new config.
         fs::rename(temp, &self.name).expect("Failed to write new Config");
+        self.version = self.disk_version();
         trace!("Saved config to: {}", self.name);
+
+        Ok(())
     }
 }
 
@@ -151,8 +210,17 @@ struct Tags {
 }
 
 impl Tags {
+    // Locks the config, first reloading it if the file on disk has changed.
+    fn lock(&self) -> MutexGuard<Config> {
+        let mut config = self.config.lock().expect("Failed to lock Config");
+        if let Err(err) = config.refresh() {
+            warn!("Failed to reload tags: {}", err);
+        }
+        config
+    }
+
     fn get_possible_tags(&self, guild: Option<GuildId>) -> HashMap<String, Tag> {
-        let config = self.config.lock().expect("Failed to lock Config");
+        let config = self.lock();
         let generic = config.get("generic")
             .cloned()
             .unwrap_or_else(HashMap::new);
@@ -175,31 +243,36 @@ impl Tags {
             .ok_or_else(|| "Tag not found".to_owned())
     }
 
-    fn put_tag(&self, guild: Option<GuildId>, name: String, tag: Tag) {
+    fn put_tag(&self, guild: Option<GuildId>, name: String, tag: Tag) -> Result<(), String> {
         // Load the actual tag so we can modify it.
-        let mut config = TAGS.config
-            .lock()
-            .expect("Failed to lock Config");
-        {
-            let database = config.tags
-                .get_mut(&get_database_location(guild))
-                .unwrap();
-            database.insert(name, tag);
-        }
-        config.save();
-    }
-
-    fn delete_tag(&self, guild: Option<GuildId>, name: &str) {
-        let mut config = TAGS.config
-            .lock()
-            .expect("Failed to lock Config");
-        {
-            let database = config.tags
-                .get_mut(&get_database_location(guild))
-                .unwrap();
-            database.remove(name);
+        let mut config = self.lock();
+        match config.tags.get_mut(&get_database_location(guild)) {
+            Some(database) => database.insert(name, tag),
+            None => return Err("Tag not found".to_owned()),
+        };
+        config.save()
+    }
+
+    fn delete_tag(&self, guild: Option<GuildId>, name: &str) -> Result<(), String> {
+        let mut config = self.lock();
+        match config.tags.get_mut(&get_database_location(guild)) {
+            Some(database) => database.remove(name),
+            None => return Err("Tag not found".to_owned()),
+        };
+        config.save()
+    }
+
+    // Increments the use count of a tag in the database which it belongs to,
+    // which is not necessarily that of the guild it was used in.
+    fn record_use(&self, name: &str, tag: &Tag) -> Result<(), String> {
+        let location = tag.location.clone().unwrap_or_else(|| get_database_location(None));
+        let mut config = self.lock();
+        match config.tags.get_mut(&location).and_then(|database| database.get_mut(name)) {
+            Some(stored) => stored.uses += 1,
+            // The tag was deleted in the meantime.
+            None => return Ok(()),
         }
-        config.save();
+        config.save()
     }
 }
 
@@ -212,24 +285,10 @@ command!(tag(context, message, args) {
         Some("list") => list,
         Some("edit") => edit,
         Some("delete") => delete,
-        Some(name) => {
-            return {
-                let guild_id = message.guild_id();
-
-                let lookup = name.to_lowercase();
-                match TAGS.get_tag(guild_id, lookup.clone()) {
-                    Ok(tag) => {
-                        let mut tag = tag.clone();
-                        tag.uses += 1;
-                        TAGS.put_tag(guild_id, lookup, tag.clone());
-                        check_msg(context.say(&tag.content));
-
-                        Ok(())
-                    },
-                    Err(err) => Err(err),
-                }
-            };
-        },
+        Some("limits") => limits,
+        Some("shortcut") => shortcut,
+        Some("reload") => reload,
+        Some(name) => return invoke(context, message.guild_id(), name),
         None => {
             return Err("Either specify a tag name or use one of the available commands."
                 .to_owned());
@@ -244,6 +303,123 @@ command!(tag(context, message, args) {
     }
 });
 
+command!(autoresponder(context, message, args) {
+    let mut args = args.into_iter();
+
+    let f = match args.next().as_ref().map(String::as_ref) {
+        Some("add") => autoresponder_add,
+        Some("list") => autoresponder_list,
+        Some("remove") => autoresponder_remove,
+        _ => {
+            return Err("Please use one of the available commands: \"add\", \"list\", or \
+                        \"remove\"."
+                .to_owned());
+        },
+    };
+
+    match f(context, message, args.collect()) {
+        Ok(()) => {},
+        v => return v,
+    }
+});
+
+/// Handles messages which are not commands, replying with a tag if the message
+/// matches a tag shortcut or one of the guild's autoresponders.
+pub fn handle_message(context: &Context, message: &Message) {
+    if message.author.bot || message.content.starts_with(&CONFIG.command_prefix) {
+        return;
+    }
+
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let settings = match SETTINGS.lock()
+        .expect("Failed to lock tag settings")
+        .get()
+        .get(&guild_id.to_string())
+        .cloned() {
+        Some(settings) => settings,
+        None => return,
+    };
+
+    if let Some(ref prefix) = settings.shortcut_prefix {
+        if let Some(name) = shortcut_name(&message.content, prefix) {
+            if let Err(err) = invoke(context, Some(guild_id), name) {
+                trace!("Ignoring tag shortcut \"{}\": {}", name, err);
+            }
+            return;
+        }
+    }
+
+    for responder in &settings.autoresponders {
+        if !pattern_matches(&responder.pattern, &message.content) {
+            continue;
+        }
+
+        // Only reply if the channel is not on cooldown.
+        let mut cooldowns = COOLDOWNS.lock().expect("Failed to lock autoresponder cooldowns");
+        let cooldown = Duration::from_secs(CONFIG.autoresponder_cooldown);
+        if let Some(last) = cooldowns.get(&message.channel_id) {
+            if last.elapsed() < cooldown {
+                return;
+            }
+        }
+
+        if let Err(err) = invoke(context, Some(guild_id), &responder.tag) {
+            warn!("Autoresponder for tag \"{}\" failed: {}", responder.tag, err);
+        }
+        cooldowns.insert(message.channel_id, Instant::now());
+        return;
+    }
+}
+
+// Replies with the content of the specified tag, incrementing its use count.
+fn invoke(context: &Context, guild_id: Option<GuildId>, name: &str) -> Result<(), String> {
+    let lookup = name.to_lowercase();
+    let tag = TAGS.get_tag(guild_id, lookup.clone())?;
+    TAGS.record_use(&lookup, &tag)?;
+    check_msg(context.say(&tag.content));
+
+    Ok(())
+}
+
+// Returns the name of the tag invoked by a message consisting only of the
+// shortcut prefix followed by a tag name, if any.
+fn shortcut_name<'a>(content: &'a str, prefix: &str) -> Option<&'a str> {
+    let content = content.trim();
+    if !content.starts_with(prefix) {
+        return None;
+    }
+
+    let name = &content[prefix.len()..];
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+// Returns whether text matches an autoresponder pattern. Each pattern is only
+// compiled the first time it is added or used.
+fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let mut patterns = PATTERNS.lock().expect("Failed to lock autoresponder patterns");
+    if let Some(regex) = patterns.get(pattern) {
+        return regex.as_ref().map_or(false, |regex| regex.is_match(text));
+    }
+
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => Some(regex),
+        Err(err) => {
+            warn!("Invalid autoresponder pattern \"{}\": {}", pattern, err);
+            None
+        },
+    };
+    let matches = regex.as_ref().map_or(false, |regex| regex.is_match(text));
+    patterns.insert(pattern.to_owned(), regex);
+    matches
+}
+
 pub fn create(context: &Context, message: &Message, args: Vec<String>) -> Result<(), String> {
     let mut args = args.into_iter();
 
@@ -263,7 +439,10 @@ pub fn create(context: &Context, message: &Message, args: Vec<String>) -> Result
     verify_tag_name(&name)?;
 
     let location = get_database_location(message.guild_id());
-    let mut config = TAGS.config.lock().expect("Failed to lock Config");
+    let limits = get_limits(&location);
+    verify_tag_content(&content, &limits)?;
+
+    let mut config = TAGS.lock();
     let mut database = config.get(&location)
         .cloned()
         .unwrap_or_else(HashMap::new);
@@ -271,6 +450,19 @@ pub fn create(context: &Context, message: &Message, args: Vec<String>) -> Result
         return Err("Tag already exists.".to_owned());
     }
 
+    if database.len() >= limits.per_guild {
+        return Err(format!("This server has reached its limit of {} tags.", limits.per_guild));
+    }
+
+    let owned = database.values()
+        .filter(|tag| tag.owner_id == message.author.id.0)
+        .count();
+    if owned >= limits.per_user {
+        return Err(format!("You have reached the limit of {} tags on this server. Delete one \
+                            of your existing tags before creating another.",
+                           limits.per_user));
+    }
+
     database.insert(name.clone(),
                     Tag::new(name.clone(),
                              content,
@@ -278,7 +470,7 @@ pub fn create(context: &Context, message: &Message, args: Vec<String>) -> Result
                              None,
                              Some(location.clone()),
                              None));
-    config.insert(location, database);
+    config.insert(location, database)?;
     check_msg(context.say(&format!("Tag \"{}\" successfully created.", name)));
 
     Ok(())
@@ -345,9 +537,10 @@ pub fn edit(context: &Context, message: &Message, args: Vec<String>) -> Result<(
     } else {
         content.join(" ")
     };
+    verify_tag_content(&content, &get_limits(&get_database_location(guild_id)))?;
 
     tag.content = content;
-    TAGS.put_tag(guild_id, name.clone(), tag);
+    TAGS.put_tag(guild_id, name.clone(), tag)?;
 
     check_msg(context.say(&format!("Tag \"{}\" successfully updated.", name)));
 
@@ -374,13 +567,246 @@ pub fn delete(context: &Context, message: &Message, args: Vec<String>) -> Result
         return Err("You do not have permission to do that.".to_owned());
     }
 
-    TAGS.delete_tag(guild_id, &name);
+    TAGS.delete_tag(guild_id, &name)?;
 
     check_msg(context.say(&format!("Tag \"{}\" successfully deleted.", name)));
 
     Ok(())
 }
 
+pub fn reload(context: &Context, message: &Message, _args: Vec<String>) -> Result<(), String> {
+    if !CONFIG.owners.contains(&message.author.id.0) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    let count = {
+        let mut config = TAGS.config.lock().expect("Failed to lock Config");
+        config.load()?;
+        config.tags.values().map(HashMap::len).sum::<usize>()
+    };
+    check_msg(context.say(&format!("Reloaded {} tags.", count)));
+
+    Ok(())
+}
+
+pub fn limits(context: &Context, message: &Message, args: Vec<String>) -> Result<(), String> {
+    let location = get_database_location(message.guild_id());
+    let mut args = args.into_iter();
+
+    let limit = match args.next() {
+        Some(limit) => limit,
+        None => {
+            let limits = get_limits(&location);
+            check_msg(context.say(&format!("Tags per user: {}\nTags per server: {}\nMaximum \
+                                            content length: {}",
+                                           limits.per_user,
+                                           limits.per_guild,
+                                           limits.content_length)));
+            return Ok(());
+        },
+    };
+
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    let value = match args.next().as_ref().map(String::as_ref) {
+        Some("default") => None,
+        Some(value) => {
+            match value.parse::<usize>() {
+                Ok(value) => Some(value),
+                Err(_) => return Err("Please specify a valid number, or \"default\".".to_owned()),
+            }
+        },
+        None => return Err("Please specify a new value for the limit.".to_owned()),
+    };
+
+    let mut settings = SETTINGS.lock().expect("Failed to lock tag settings");
+    {
+        let guild = settings.get_mut()
+            .entry(location)
+            .or_insert_with(GuildSettings::default);
+        match limit.as_ref() {
+            "user" => guild.max_tags_per_user = value,
+            "server" | "guild" => guild.max_tags_per_guild = value,
+            "length" => guild.max_content_length = value,
+            _ => {
+                return Err("Please specify one of the limits \"user\", \"server\", or \
+                            \"length\"."
+                    .to_owned())
+            },
+        }
+    }
+    settings.save();
+
+    check_msg(context.say("Tag limit successfully updated."));
+
+    Ok(())
+}
+
+pub fn shortcut(context: &Context, message: &Message, args: Vec<String>) -> Result<(), String> {
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Err("Tag shortcuts can only be used in a server.".to_owned()),
+    };
+
+    let prefix = match args.into_iter().next() {
+        Some(prefix) => prefix,
+        None => {
+            let settings = SETTINGS.lock().expect("Failed to lock tag settings");
+            let response = match settings.get()
+                .get(&guild_id.to_string())
+                .and_then(|s| s.shortcut_prefix.as_ref()) {
+                Some(prefix) => format!("Tag shortcuts are enabled with the prefix `{}`.", prefix),
+                None => "Tag shortcuts are disabled.".to_owned(),
+            };
+            check_msg(context.say(&response));
+            return Ok(());
+        },
+    };
+
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    let prefix = parse_shortcut_prefix(&prefix, &CONFIG.command_prefix)?;
+
+    let response = match prefix {
+        Some(ref prefix) => format!("Tag shortcuts enabled with the prefix `{}`.", prefix),
+        None => "Tag shortcuts disabled.".to_owned(),
+    };
+
+    let mut settings = SETTINGS.lock().expect("Failed to lock tag settings");
+    settings.get_mut()
+        .entry(guild_id.to_string())
+        .or_insert_with(GuildSettings::default)
+        .shortcut_prefix = prefix;
+    settings.save();
+
+    check_msg(context.say(&response));
+
+    Ok(())
+}
+
+// Parses the argument to `tag shortcut`, returning the new shortcut prefix, or
+// `None` if shortcuts are to be disabled.
+fn parse_shortcut_prefix(arg: &str, command_prefix: &str) -> Result<Option<String>, String> {
+    match arg {
+        "off" => Ok(None),
+        "on" => Ok(Some(DEFAULT_SHORTCUT_PREFIX.to_owned())),
+        prefix if prefix.starts_with(command_prefix) => {
+            Err("The shortcut prefix cannot start with the command prefix.".to_owned())
+        },
+        prefix => Ok(Some(prefix.to_owned())),
+    }
+}
+
+pub fn autoresponder_add(
+    context: &Context,
+    message: &Message,
+    args: Vec<String>
+) -> Result<(), String> {
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Err("Autoresponders can only be used in a server.".to_owned()),
+    };
+
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    // The last argument is the tag name, and everything before it forms the
+    // pattern, so that patterns may contain spaces.
+    let mut args = args;
+    let name = match args.pop() {
+        Some(name) => name.trim().to_lowercase(),
+        None => return Err("Please specify a pattern and a tag name.".to_owned()),
+    };
+    if args.is_empty() {
+        return Err("Please specify a pattern for the autoresponder.".to_owned());
+    }
+    let pattern = args.join(" ");
+
+    let regex = Regex::new(&pattern).map_err(|err| format!("Invalid pattern: {}", err))?;
+    TAGS.get_tag(Some(guild_id), name.clone())?;
+    PATTERNS.lock()
+        .expect("Failed to lock autoresponder patterns")
+        .insert(pattern.clone(), Some(regex));
+
+    let mut settings = SETTINGS.lock().expect("Failed to lock tag settings");
+    settings.get_mut()
+        .entry(guild_id.to_string())
+        .or_insert_with(GuildSettings::default)
+        .autoresponders
+        .push(Autoresponder {
+            pattern: pattern,
+            tag: name.clone(),
+        });
+    settings.save();
+
+    check_msg(context.say(&format!("Autoresponder for tag \"{}\" successfully added.", name)));
+
+    Ok(())
+}
+
+pub fn autoresponder_list(
+    context: &Context,
+    message: &Message,
+    _args: Vec<String>
+) -> Result<(), String> {
+    let location = get_database_location(message.guild_id());
+    let settings = SETTINGS.lock().expect("Failed to lock tag settings");
+    let responders = settings.get()
+        .get(&location)
+        .map(|s| s.autoresponders.clone())
+        .unwrap_or_else(Vec::new);
+
+    let response = if responders.is_empty() {
+        "No autoresponders configured.".to_owned()
+    } else {
+        responders.iter()
+            .enumerate()
+            .map(|(i, r)| format!("{}. `{}` -> {}", i + 1, r.pattern, r.tag))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    check_msg(context.say(&response));
+
+    Ok(())
+}
+
+pub fn autoresponder_remove(
+    context: &Context,
+    message: &Message,
+    args: Vec<String>
+) -> Result<(), String> {
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Err("Autoresponders can only be used in a server.".to_owned()),
+    };
+
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    let index = match args.iter().next().and_then(|i| i.parse::<usize>().ok()) {
+        Some(index) if index > 0 => index - 1,
+        _ => return Err("Please specify the number of the autoresponder to remove.".to_owned()),
+    };
+
+    let mut settings = SETTINGS.lock().expect("Failed to lock tag settings");
+    let removed = match settings.get_mut().get_mut(&guild_id.to_string()) {
+        Some(guild) if index < guild.autoresponders.len() => guild.autoresponders.remove(index),
+        _ => return Err("Autoresponder not found.".to_owned()),
+    };
+    settings.save();
+
+    check_msg(context.say(&format!("Autoresponder for tag \"{}\" successfully removed.",
+                                   removed.tag)));
+
+    Ok(())
+}
+
 // Denies certain tag names from being used as keys.
 fn verify_tag_name(name: &str) -> Result<(), String> {
     if name.contains("@everyone") || name.contains("@here") {
@@ -394,6 +820,36 @@ fn verify_tag_name(name: &str) -> Result<(), String> {
     Ok(())
 }
 
+// Denies tag content which exceeds the configured length limit.
+fn verify_tag_content(content: &str, limits: &TagLimits) -> Result<(), String> {
+    if content.chars().count() > limits.content_length {
+        return Err(format!("Tag content limit is {} characters", limits.content_length));
+    }
+
+    Ok(())
+}
+
+// Returns the tag limits in effect for the specified database location, taking
+// into account any overrides set by the guild's administrators.
+fn get_limits(location: &str) -> TagLimits {
+    let settings = SETTINGS.lock().expect("Failed to lock tag settings");
+    apply_overrides(CONFIG.tag_limits.clone(), settings.get().get(location))
+}
+
+// Replaces any limits which a guild's administrators have overridden.
+fn apply_overrides(limits: TagLimits, guild: Option<&GuildSettings>) -> TagLimits {
+    match guild {
+        Some(guild) => {
+            TagLimits {
+                per_user: guild.max_tags_per_user.unwrap_or(limits.per_user),
+                per_guild: guild.max_tags_per_guild.unwrap_or(limits.per_guild),
+                content_length: guild.max_content_length.unwrap_or(limits.content_length),
+            }
+        },
+        None => limits,
+    }
+}
+
 fn owner_check(message: &Message, tag: &Tag) -> bool {
     message.author.id == tag.owner_id
 }
@@ -402,3 +858,135 @@ fn get_database_location(guild: Option<GuildId>) -> String {
     guild.map(|g| g.to_string())
         .unwrap_or_else(|| "generic".to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use config::TagLimits;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use super::{Config, GuildSettings, apply_overrides, parse_shortcut_prefix, pattern_matches,
+                shortcut_name, verify_tag_content};
+
+    fn limits() -> TagLimits {
+        TagLimits {
+            per_user: 5,
+            per_guild: 20,
+            content_length: 10,
+        }
+    }
+
+    #[test]
+    fn test_verify_tag_content() {
+        assert!(verify_tag_content("short", &limits()).is_ok());
+        assert!(verify_tag_content("exactly 10", &limits()).is_ok());
+        assert_eq!(verify_tag_content("eleven long", &limits()),
+                   Err("Tag content limit is 10 characters".to_owned()));
+    }
+
+    #[test]
+    fn test_verify_tag_content_counts_characters() {
+        // Ten characters, but more than ten bytes.
+        assert!(verify_tag_content("éééééééééé", &limits()).is_ok());
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let guild = GuildSettings {
+            max_tags_per_user: Some(1),
+            max_content_length: Some(100),
+            ..GuildSettings::default()
+        };
+
+        let limits = apply_overrides(limits(), Some(&guild));
+        assert_eq!(limits.per_user, 1);
+        assert_eq!(limits.per_guild, 20);
+        assert_eq!(limits.content_length, 100);
+    }
+
+    #[test]
+    fn test_apply_overrides_without_guild_settings() {
+        let limits = apply_overrides(limits(), None);
+        assert_eq!(limits.per_user, 5);
+        assert_eq!(limits.per_guild, 20);
+        assert_eq!(limits.content_length, 10);
+
+        let limits = apply_overrides(limits, Some(&GuildSettings::default()));
+        assert_eq!(limits.per_user, 5);
+    }
+
+    #[test]
+    fn test_shortcut_name() {
+        assert_eq!(shortcut_name("!!rules", "!!"), Some("rules"));
+        assert_eq!(shortcut_name("  !!rules  ", "!!"), Some("rules"));
+        assert_eq!(shortcut_name("?rules", "?"), Some("rules"));
+    }
+
+    #[test]
+    fn test_shortcut_name_rejects_other_messages() {
+        assert_eq!(shortcut_name("!!", "!!"), None);
+        assert_eq!(shortcut_name("!!two words", "!!"), None);
+        assert_eq!(shortcut_name("rules", "!!"), None);
+        assert_eq!(shortcut_name("see !!rules", "!!"), None);
+    }
+
+    #[test]
+    fn test_parse_shortcut_prefix() {
+        assert_eq!(parse_shortcut_prefix("off", ";"), Ok(None));
+        assert_eq!(parse_shortcut_prefix("on", ";"), Ok(Some("!!".to_owned())));
+        assert_eq!(parse_shortcut_prefix("?", ";"), Ok(Some("?".to_owned())));
+        assert!(parse_shortcut_prefix(";;", ";").is_err());
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches(r"(?i)^hello\b", "Hello there"));
+        assert!(!pattern_matches(r"(?i)^hello\b", "well, hello"));
+        // Invalid patterns never match.
+        assert!(!pattern_matches(r"(unclosed", "(unclosed"));
+        assert!(!pattern_matches(r"(unclosed", "(unclosed"));
+    }
+
+    #[test]
+    fn test_config_save() {
+        let path = env::temp_dir().join("smexybot-tags-save.json");
+        let _ = fs::remove_file(&path);
+
+        let mut config = Config::new(path.to_str().unwrap());
+        config.insert("generic".to_owned(), HashMap::new()).unwrap();
+        config.insert("1234".to_owned(), HashMap::new()).unwrap();
+
+        let reloaded = Config::new(path.to_str().unwrap());
+        assert!(reloaded.get("generic").is_some());
+        assert!(reloaded.get("1234").is_some());
+    }
+
+    #[test]
+    fn test_config_save_refuses_external_changes() {
+        let path = env::temp_dir().join("smexybot-tags-external.json");
+        let _ = fs::remove_file(&path);
+
+        let mut config = Config::new(path.to_str().unwrap());
+        config.insert("generic".to_owned(), HashMap::new()).unwrap();
+
+        // Simulate an edit made by another program. The length differs, so the
+        // change is detected even if the modification time does not.
+        let external = r#"{"5678": {}, "9012": {}}"#;
+        File::create(&path).unwrap().write_all(external.as_bytes()).unwrap();
+
+        assert!(config.insert("1234".to_owned(), HashMap::new()).is_err());
+
+        // The external changes were kept, and have been reloaded.
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, external);
+        assert!(config.get("5678").is_some());
+        assert!(config.get("generic").is_none());
+        assert!(config.get("1234").is_none());
+
+        // Now that the config is up to date, saving succeeds again.
+        config.insert("1234".to_owned(), HashMap::new()).unwrap();
+        assert!(Config::new(path.to_str().unwrap()).get("1234").is_some());
+    }
+}
@@ -0,0 +1,10 @@
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CacheEntry {
+    response: String,
+    created_at: DateTime<UTC>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct UserSettings {
+    units: Option<String>,
+}
@@ -8,49 +8,144 @@
 // except according to those terms.
 
 //! Provides the a command which allows a user to query the Wolfram|Alpha API.
+//!
+//! Successful responses are cached on disk for a configurable period, so that
+//! repeated queries do not count against the API quota. The cache may be
+//! bypassed for time-sensitive queries with the `--nocache` flag.
+//!
+//! Results are shown one pod per page, and may be navigated by reacting to
+//! them. Units and location may be specified with the `--units` and
+//! `--location` flags, and users may pick an alternative interpretation of
+//! their query by replying with its number.
+//!
+//! For quick questions, the `--short` flag returns a one-line answer from the
+//! Short Answers API, and the `--text` flag shows every plaintext pod in a
+//! single code block. A single pod may be requested by its title with the
+//! `--pod` flag.
+//!
+//! The `wolfram cache [purge]` and `wolfram units <metric|imperial|default>`
+//! subcommands are only recognized when followed by exactly those arguments,
+//! so that queries such as `wolfram units of force` are still answered.
 
 extern crate wolfram_alpha;
 
-use hyper::Client;
-use self::wolfram_alpha::Error as WolframError;
+use ::CONFIG;
+use chrono::{DateTime, Duration, UTC};
+#[cfg(feature = "calc")]
+use command::calc;
+use command::quota::{self, Caller, Service};
+use hyper::{Client, Url};
+use hyper::status::StatusCode;
+use job;
+use self::wolfram_alpha::{Error as WolframError, Result as WolframResult};
+use self::wolfram_alpha::{HttpRequestError, WolframAlphaRequestSender};
 use self::wolfram_alpha::model::{Pod, QueryResult};
-use serenity::utils::builder::{CreateEmbed, CreateEmbedField};
+use serenity::client::Context;
+use serenity::model::{ChannelId, Message, MessageId, Reaction, ReactionType, UserId};
+use serenity::utils::Colour;
+use serenity::utils::builder::CreateEmbed;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error as StdError;
+use std::io::Read;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration as StdDuration, Instant};
+use store::Store;
 use util::{check_msg, random_colour, stringify};
 
 lazy_static! {
-    static ref PLUGIN: WolframPlugin = {
-        let api_app_id = env::var("WOLFRAM_ALPHA_API_APP_ID")
-            .expect("WOLFRAM_ALPHA_API_APP_ID env var not set");
-        WolframPlugin::new(api_app_id)
-    };
+    // The plugin is only available if an app ID has been configured.
+    static ref PLUGIN: Option<WolframPlugin> = env::var(APP_ID_VAR).ok().map(WolframPlugin::new);
+    static ref PAGINATIONS: Mutex<HashMap<MessageId, Pagination>> = Mutex::new(HashMap::new());
+    static ref PENDING: Mutex<HashMap<(ChannelId, UserId), PendingChoice>> =
+        Mutex::new(HashMap::new());
+    static ref USER_SETTINGS: Mutex<Store<HashMap<String, UserSettings>>> =
+        Mutex::new(Store::new("wolfram_users.json"));
 }
 
+/// The environment variable containing the Wolfram|Alpha app ID.
+pub const APP_ID_VAR: &'static str = "WOLFRAM_ALPHA_API_APP_ID";
+
+// The maximum number of alternative interpretations offered for a query.
+const MAX_CHOICES: usize = 10;
+// The number of seconds for which an interpretation can be chosen.
+const CHOICE_TIMEOUT: u64 = 60;
+
+const NEXT_PAGE: &'static str = "\u{25b6}";
+const PREVIOUS_PAGE: &'static str = "\u{25c0}";
+// The number of seconds for which results can be navigated.
+const PAGINATION_TIMEOUT: u64 = 15 * 60;
+
+#[cfg(feature = "nightly")]
+include!("wolfram_alpha.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/wolfram_alpha.rs"));
+
 pub struct WolframPlugin {
     app_id: String,
+    api_url: String,
+    short_answer_url: String,
     hyper_client: Client,
+    cache: Mutex<Store<HashMap<String, CacheEntry>>>,
+    cache_hits: AtomicUsize,
+    cache_misses: AtomicUsize,
 }
 
 impl WolframPlugin {
     /// Returns a new instance of `WolframPlugin`.
     pub fn new(wolfram_alpha_api_app_id: String) -> Self {
+        WolframPlugin::with_urls(wolfram_alpha_api_app_id,
+                                 CONFIG.wolfram.api_url.clone(),
+                                 CONFIG.wolfram.short_answer_url.clone(),
+                                 "wolfram_cache.json")
+    }
+
+    // Returns a new instance of `WolframPlugin` which sends its requests to
+    // the specified URLs, and caches responses in the specified file.
+    fn with_urls(
+        app_id: String,
+        api_url: String,
+        short_answer_url: String,
+        cache_file: &str
+    ) -> Self {
         WolframPlugin {
-            app_id: wolfram_alpha_api_app_id,
+            app_id: app_id,
+            api_url: api_url,
+            short_answer_url: short_answer_url,
             hyper_client: Client::new(),
+            cache: Mutex::new(Store::load_or_default(cache_file)),
+            cache_hits: AtomicUsize::new(0),
+            cache_misses: AtomicUsize::new(0),
         }
     }
 
-    fn query(&self, args: &[String]) -> Result<QueryResult, String> {
-        let query = match args.len() {
-            0 => return Err("Missing WolframAlpha query".to_owned()),
-            _ => args.join(" "),
-        };
+    // Queries Wolfram|Alpha. Requests which are not served from the cache
+    // count against the quota of the caller, if there is one.
+    fn query(
+        &self,
+        query: &str,
+        params: HashMap<&str, &str>,
+        use_cache: bool,
+        caller: Option<&Caller>
+    ) -> Result<QueryResult, String> {
         trace!("WolframAlpha query: {}", query);
 
-        match wolfram_alpha::query::query(&self.hyper_client, &self.app_id, &query, None) {
+        let sender = CachingSender {
+            plugin: self,
+            use_cache: use_cache,
+            caller: caller,
+            quota_error: RefCell::new(None),
+        };
+        match wolfram_alpha::query::query(&sender, &self.app_id, query, Some(params)) {
             Ok(query_result) => Ok(query_result),
             Err(e) => {
+                if let Some(err) = sender.quota_error.borrow_mut().take() {
+                    return Err(err);
+                }
+
                 let description = match e {
                     WolframError::Xml(_) => "failed to parse response",
                     _ => e.description(),
@@ -59,137 +154,934 @@ impl WolframPlugin {
             },
         }
     }
-}
 
-command!(wolfram(context, message, args) {
-    context.broadcast_typing(message.channel_id).map_err(stringify)?;
-
-    match PLUGIN.query(&args) {
-        Ok(query_result) => {
-            if query_result.success {
-                // Format the `QueryResult` into Discord-ready output.
-                let colour = random_colour();
-                let pods = query_result.pod
-                    .ok_or_else(|| "Result did not contain any parsable information")?;
-                check_msg(context.send_message(
-                    message.channel_id,
-                    |m| m.embed(|e| format_pods(&pods, e).colour(colour)),
-                ));
-            } else if let Some(didyoumeans) = query_result.didyoumeans {
-                let colour = random_colour();
-                check_msg(context.send_message(message.channel_id, |m| {
-                    m.embed(|e| {
-                        e.title("Query unsuccessful.")
-                            .colour(colour)
-                            .field(|f| {
-                                let field = f.name("Did you mean:");
-
-                                let mut description = String::new();
-                                for item in &didyoumeans.didyoumean {
-                                    description.push_str(&format!("* {}\n", item.value));
-                                }
-                                field.value(description.as_str())
-                            })
-                    })
-                }));
-            } else if let Some(error) = query_result.error {
-                let colour = random_colour();
-                check_msg(context.send_message(message.channel_id, |m| {
-                    m.embed(|e| {
-                        e.title("Wolfram|Alpha returned an error.")
-                            .colour(colour)
-                            .field(|f| {
-                                let field = f.name("Error");
-
-                                let description = format!(
-                                    "Code: {}\nMessage: {}",
-                                    error.code,
-                                    error.msg,
-                                );
-                                field.value(description.as_str())
-                            })
-                    })
-                }));
+    // Queries the Short Answers API, which responds with a single line of
+    // plain text rather than a full result.
+    fn short_answer(
+        &self,
+        query: &str,
+        params: HashMap<&str, &str>,
+        use_cache: bool,
+        caller: Option<&Caller>
+    ) -> Result<String, String> {
+        trace!("WolframAlpha short answer query: {}", query);
+
+        let mut params = params.into_iter()
+            .filter(|&(key, _)| key == "units" || key == "location")
+            // The Short Answers API calls non-metric units "imperial".
+            .map(|(key, value)| (key, if value == "nonmetric" { "imperial" } else { value }))
+            .collect::<HashMap<&str, &str>>();
+        params.insert("i", query);
+
+        let key = cache_key(&self.short_answer_url, &params);
+        if use_cache {
+            if let Some(response) = self.cached_response(&key) {
+                trace!("Wolfram|Alpha cache hit: {}", key);
+                self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(response);
+            }
+        }
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        if let Some(caller) = caller {
+            quota::consume(Service::Wolfram, caller)?;
+        }
+
+        let mut url = Url::parse(&self.short_answer_url).map_err(stringify)?;
+        {
+            let mut pairs = url.query_pairs_mut();
+            pairs.append_pair("appid", &self.app_id);
+            for (key, value) in &params {
+                pairs.append_pair(key, value);
+            }
+        }
+
+        let mut response = self.hyper_client.get(url).send().map_err(stringify)?;
+        let mut answer = String::new();
+        response.read_to_string(&mut answer).map_err(stringify)?;
+        match response.status {
+            StatusCode::Ok => {
+                self.cache_response(key, answer.clone());
+                Ok(answer)
+            },
+            StatusCode::NotImplemented => {
+                Err("Wolfram|Alpha has no short answer for that query. Try `wolfram --text` or \
+                     `wolfram` instead."
+                    .to_owned())
+            },
+            _ => Err(format!("Failed to query WolframAlpha: {}", answer.trim())),
+        }
+    }
+
+    // Sends a request for an API method, e.g. `query`, to the configured
+    // Wolfram|Alpha API.
+    fn request(&self, method: &str, params: &HashMap<&str, &str>) -> WolframResult<String> {
+        let mut url = Url::parse(&format!("{}{}", self.api_url, method))
+            .map_err(|err| HttpRequestError::Other(Box::new(err)))?;
+        {
+            let mut pairs = url.query_pairs_mut();
+            for (key, value) in params {
+                pairs.append_pair(key, value);
+            }
+        }
+        trace!("Sending Wolfram|Alpha request to: {}", url);
+
+        let mut response = self.hyper_client
+            .get(url)
+            .send()
+            .map_err(|err| HttpRequestError::Other(Box::new(err)))?;
+        let mut result = String::new();
+        response.read_to_string(&mut result).map_err(HttpRequestError::from)?;
+
+        Ok(result)
+    }
+
+    // Returns the cached response for a request, if there is one which has
+    // not expired.
+    fn cached_response(&self, key: &str) -> Option<String> {
+        let cache = self.cache.lock().expect("Failed to lock Wolfram|Alpha cache");
+        cache.get()
+            .get(key)
+            .and_then(|entry| if is_expired(entry) {
+                None
             } else {
-                check_msg(context.say("Query was unsuccessful. Perhaps try rewording it?"));
+                Some(entry.response.clone())
+            })
+    }
+
+    fn cache_response(&self, key: String, response: String) {
+        let mut cache = self.cache.lock().expect("Failed to lock Wolfram|Alpha cache");
+        {
+            let entries = cache.get_mut();
+            // Drop expired entries, so that the cache does not grow forever.
+            let expired = entries.iter()
+                .filter(|&(_, entry)| is_expired(entry))
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<String>>();
+            for key in expired {
+                entries.remove(&key);
             }
-        },
-        Err(err) => check_msg(context.say(err.as_ref())),
+
+            entries.insert(key,
+                           CacheEntry {
+                               response: response,
+                               created_at: UTC::now(),
+                           });
+        }
+        cache.save();
     }
-});
 
-fn format_pods(pods: &[Pod], embed: CreateEmbed) -> CreateEmbed {
-    let mut iter = pods.iter();
-
-    // First result is the interpretation.
-    let interpretation = iter.next()
-            .unwrap()
-            .subpod[0]
-        .plaintext
-        .clone()
-        .unwrap_or("".to_owned());
-    let mut embed = embed.title("Input interpretation");
-    embed = embed.description(&format!("`{}`", interpretation));
-
-    if let Some(img) = pods.iter()
-        .skip(1)
-        .filter_map(|p| p.subpod.iter().filter_map(|s| s.img.clone()).next())
-        .next() {
-        embed = embed.image(unescape(img.src.as_str()).as_ref());
-    }
-
-    // If there is a primary pod, then only format and print that pod.
-    if let Some(pod) = pods.iter().find(|p| p.primary == Some(true)) {
-        embed = embed.field(|f| format_pod(pod, f));
+    fn purge_cache(&self) -> usize {
+        let mut cache = self.cache.lock().expect("Failed to lock Wolfram|Alpha cache");
+        let count = cache.get().len();
+        cache.get_mut().clear();
+        cache.save();
+        count
+    }
+}
+
+// Sends requests to the Wolfram|Alpha API, serving responses from the cache
+// where possible.
+struct CachingSender<'a> {
+    plugin: &'a WolframPlugin,
+    use_cache: bool,
+    caller: Option<&'a Caller>,
+    // Set if a request was refused because the caller exceeded their quota,
+    // as the reason cannot be passed through the `wolfram_alpha` crate.
+    quota_error: RefCell<Option<String>>,
+}
+
+impl<'b> WolframAlphaRequestSender for CachingSender<'b> {
+    fn send<'a>(&self, method: &str, params: &mut HashMap<&str, &'a str>) -> WolframResult<String> {
+        let key = cache_key(method, params);
+        if self.use_cache {
+            if let Some(response) = self.plugin.cached_response(&key) {
+                trace!("Wolfram|Alpha cache hit: {}", key);
+                self.plugin.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(response);
+            }
+        }
+        self.plugin.cache_misses.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(caller) = self.caller {
+            if let Err(err) = quota::consume(Service::Wolfram, caller) {
+                *self.quota_error.borrow_mut() = Some(err.clone());
+                return Err(HttpRequestError::Other(err.into()).into());
+            }
+        }
+
+        let response = self.plugin.request(method, params)?;
+        // Only successful results are cached, so that transient errors are not
+        // repeated for the lifetime of the cache entry.
+        if response.contains("success='true'") {
+            self.plugin.cache_response(key, response.clone());
+        }
+
+        Ok(response)
+    }
+}
+
+/// Returns the number of Wolfram|Alpha cache hits and misses since startup.
+pub fn cache_stats() -> (usize, usize) {
+    PLUGIN.as_ref().map_or((0, 0), |plugin| {
+        (plugin.cache_hits.load(Ordering::Relaxed), plugin.cache_misses.load(Ordering::Relaxed))
+    })
+}
+
+/// Returns whether the Wolfram|Alpha app ID has been configured. If not, the
+/// `disabled` command should be registered in place of `wolfram`.
+pub fn is_enabled() -> bool {
+    PLUGIN.is_some()
+}
+
+// Returns the plugin, or an explanation of why it is unavailable.
+fn plugin() -> Result<&'static WolframPlugin, String> {
+    PLUGIN.as_ref().ok_or_else(|| {
+        format!("The wolfram command is disabled because no Wolfram|Alpha app ID has been \
+                 configured. Ask the bot owner to set the {} environment variable.",
+                APP_ID_VAR)
+    })
+}
+
+// Builds the cache key for a request from its method and parameters. The app
+// ID is excluded, and the input is normalized so that trivially different
+// queries share an entry.
+fn cache_key(method: &str, params: &HashMap<&str, &str>) -> String {
+    let mut params = params.iter()
+        .filter(|&(key, _)| *key != "appid")
+        .map(|(key, value)| if *key == "input" || *key == "i" {
+            format!("{}={}", key, normalize_query(value))
+        } else {
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<String>>();
+    params.sort();
+    format!("{}?{}", method, params.join("&"))
+}
+
+fn normalize_query(query: &str) -> String {
+    query.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn is_expired(entry: &CacheEntry) -> bool {
+    UTC::now() - entry.created_at > Duration::seconds(CONFIG.wolfram.cache_ttl)
+}
+
+command!(wolfram(context, message, args) {
+    match subcommand(&args) {
+        Some("cache") => return cache(context, message, &args[1..]),
+        Some("units") => return units(context, message, &args[1..]),
+        _ => {},
+    }
+
+    let (options, args) = QueryOptions::parse(message, args)?;
+    if args.is_empty() {
+        return Err("Missing WolframAlpha query".to_owned());
+    }
+
+    let query = args.join(" ");
+    let result = if options.short {
+        short(context, message, &query, options)
     } else {
-        // Parse all the remaining pods.
-        for pod in iter {
-            embed = embed.field(|f| format_pod(pod, f.inline(false)));
+        run_query(context, message, &query, &options)
+    };
+
+    match result {
+        Ok(()) => {},
+        v => return v,
+    }
+});
+
+// Returns the subcommand invoked by the arguments, if any. Subcommands are only
+// recognized when the arguments following them match their syntax, so that
+// queries which merely start with the same word are not mistaken for them.
+fn subcommand(args: &[String]) -> Option<&str> {
+    let (name, rest) = match args.split_first() {
+        Some((name, rest)) => (name.as_str(), rest),
+        None => return None,
+    };
+
+    let matches = match name {
+        "cache" => rest.is_empty() || rest.len() == 1 && rest[0] == "purge",
+        "units" => rest.len() == 1 && (rest[0] == "default" || parse_units(&rest[0]).is_ok()),
+        _ => false,
+    };
+
+    if matches { Some(name) } else { None }
+}
+
+// Replaces the `wolfram` command when no app ID has been configured, and
+// explains why it is unavailable.
+command!(disabled(_context, _message, args) {
+    if let Err(err) = plugin() {
+        return Err(suggest_calc(err, &args.join(" ")));
+    }
+});
+
+// Adds the answer of the offline calculator to an error message, if it is
+// able to answer the query.
+#[cfg(feature = "calc")]
+fn suggest_calc(err: String, query: &str) -> String {
+    match calc::evaluate(query) {
+        Ok(result) => {
+            format!("{}\nThe offline calculator says `{}` = {} (see `{}calc`).",
+                    err,
+                    query,
+                    result,
+                    CONFIG.command_prefix)
+        },
+        Err(_) => err,
+    }
+}
+
+#[cfg(not(feature = "calc"))]
+fn suggest_calc(err: String, _query: &str) -> String {
+    err
+}
+
+/// Handles replies to a list of alternative interpretations, re-running the
+/// query with the chosen interpretation.
+pub fn handle_message(context: &Context, message: &Message) {
+    let choice = match message.content.trim().parse::<usize>() {
+        Ok(choice) => choice,
+        Err(_) => return,
+    };
+
+    let pending = {
+        let mut pending = PENDING.lock().expect("Failed to lock pending choices");
+        let key = (message.channel_id, message.author.id);
+        let (expired, len) = match pending.get(&key) {
+            Some(p) => (p.created_at.elapsed() > StdDuration::from_secs(CHOICE_TIMEOUT),
+                        p.choices.len()),
+            None => return,
+        };
+        if expired {
+            pending.remove(&key);
+            return;
+        }
+        if choice == 0 || choice > len {
+            return;
         }
+        pending.remove(&key).expect("Pending choice missing")
+    };
+
+    let (query, options) = match pending.choices[choice - 1] {
+        Choice::Assumption(_, ref input) => {
+            let mut options = pending.options.clone();
+            options.assumption = Some(input.clone());
+            (pending.query.clone(), options)
+        },
+        Choice::Query(ref query) => (query.clone(), pending.options.clone()),
+    };
+    if let Err(err) = run_query(context, message, &query, &options) {
+        check_msg(context.say(&err));
     }
+}
 
-    embed
+/// Handles reactions to paginated results, switching to the previous or next
+/// page.
+pub fn handle_reaction_add(context: &Context, reaction: &Reaction) {
+    turn_page(context, reaction, false);
 }
 
-fn format_pod(pod: &Pod, f: CreateEmbedField) -> CreateEmbedField {
-    let f = f.name(pod.title.as_ref());
+/// Handles the removal of reactions to paginated results, which also switches
+/// pages when the bot cannot remove reactions itself. Removals made by the bot
+/// are ignored.
+pub fn handle_reaction_remove(context: &Context, reaction: &Reaction) {
+    turn_page(context, reaction, true);
+}
 
-    trace!("Formatting {} subpods", pod.subpod.len());
-    let mut result = String::new();
-    for subpod in &pod.subpod {
-        let text = match subpod.plaintext {
-            // If the text field is empty, add a message indicating as such
-            // (discord doesn't like empty/just newline fields).
-            Some(ref text) if text == "" || text == "\n" => "[No text]".to_owned(),
-            // Grab all the consecutive blobs of text.
-            Some(ref text) => {
-                trace!("Adding text: {}", text);
-                text.to_owned()
-            },
-            // If there's no text, then there's usually an image.
-            _ => {
-                match subpod.img {
-                    Some(ref img) => {
-                        let url = unescape(img.src.as_ref());
-                        trace!("Adding img src: {}", url);
-                        url
-                    },
-                    None => break,
+fn turn_page(context: &Context, reaction: &Reaction, removed: bool) {
+    let (emoji, delta) = match reaction.emoji {
+        ReactionType::Unicode(ref emoji) if emoji == PREVIOUS_PAGE => (emoji, -1),
+        ReactionType::Unicode(ref emoji) if emoji == NEXT_PAGE => (emoji, 1),
+        _ => return,
+    };
+
+    let mut paginations = PAGINATIONS.lock().expect("Failed to lock paginations");
+    let pagination = match paginations.get_mut(&reaction.message_id) {
+        Some(pagination) => pagination,
+        None => return,
+    };
+    // Only the user who made the query may navigate its results.
+    if pagination.author != reaction.user_id {
+        return;
+    }
+    if removed && pagination.removed_reactions.remove(emoji) {
+        return;
+    }
+
+    let page = pagination.page as isize + delta;
+    if page < 0 || page >= pagination.pages.len() as isize {
+        return;
+    }
+    pagination.page = page as usize;
+
+    let _ = context.edit_message(reaction.channel_id,
+                                 reaction.message_id,
+                                 "",
+                                 |e| pagination.page_embed(e));
+    if removed {
+        return;
+    }
+
+    // Remove the user's reaction so that they can use it again. This fails if
+    // the bot lacks permission to manage messages, in which case removing the
+    // reaction manually also changes the page. The removal is remembered, as
+    // it causes a removal event of its own which must not change the page.
+    let deleted = context.delete_reaction(reaction.channel_id,
+                                          reaction.message_id,
+                                          Some(reaction.user_id),
+                                          reaction.emoji.clone());
+    if deleted.is_ok() {
+        pagination.removed_reactions.insert(emoji.clone());
+    }
+}
+
+// A single page of a paginated result, representing one pod.
+#[derive(Debug)]
+struct Page {
+    title: String,
+    text: String,
+    image: Option<String>,
+}
+
+impl Page {
+    fn new(pod: &Pod) -> Self {
+        let mut text = String::new();
+        for subpod in &pod.subpod {
+            if let Some(ref plaintext) = subpod.plaintext {
+                if !plaintext.trim().is_empty() {
+                    text.push_str(plaintext);
+                    text.push('\n');
                 }
-            },
+            }
+        }
+
+        // If the output is too long to fit in an embed, truncate it.
+        if text.len() > 2048 {
+            let truncation_msg = "... (output too long)";
+            let mut end = 2048 - truncation_msg.len();
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            text.push_str(truncation_msg);
+        }
+
+        Page {
+            title: pod.title.clone(),
+            text: text,
+            image: pod.subpod
+                .iter()
+                .filter_map(|s| s.img.as_ref())
+                .next()
+                .map(|img| unescape(img.src.as_str())),
+        }
+    }
+}
+
+// The state of a result which is being navigated with reactions.
+#[derive(Debug)]
+struct Pagination {
+    author: UserId,
+    colour: Colour,
+    created_at: Instant,
+    interpretation: String,
+    page: usize,
+    pages: Vec<Page>,
+    // The reactions which the bot has removed, and whose removal events have
+    // not been received yet.
+    removed_reactions: HashSet<String>,
+}
+
+impl Pagination {
+    fn page_embed(&self, embed: CreateEmbed) -> CreateEmbed {
+        let page = &self.pages[self.page];
+        let mut embed = embed.title(&page.title)
+            .colour(self.colour)
+            .footer(|f| {
+                f.text(&format!("Page {}/{} | Input: {}",
+                                self.page + 1,
+                                self.pages.len(),
+                                self.interpretation))
+            });
+        if !page.text.is_empty() {
+            embed = embed.description(&page.text);
+        }
+        if let Some(ref image) = page.image {
+            embed = embed.image(image);
+        }
+        embed
+    }
+}
+
+// Options which modify how a query is made.
+#[derive(Clone, Debug)]
+struct QueryOptions {
+    use_cache: bool,
+    units: Option<String>,
+    location: Option<String>,
+    assumption: Option<String>,
+    pod_id: Option<String>,
+    mode: Mode,
+    // Whether to ask the Short Answers API for a single line instead.
+    short: bool,
+}
+
+// The ways in which a full result can be shown.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    // One pod per page, including images.
+    Pages,
+    // All plaintext pods in a single code block.
+    Text,
+}
+
+impl QueryOptions {
+    // Splits the flags off from the query arguments. Units default to those
+    // configured by the user.
+    fn parse(message: &Message, args: Vec<String>) -> Result<(Self, Vec<String>), String> {
+        let mut options = QueryOptions {
+            use_cache: true,
+            units: USER_SETTINGS.lock()
+                .expect("Failed to lock Wolfram|Alpha user settings")
+                .get()
+                .get(&message.author.id.0.to_string())
+                .and_then(|settings| settings.units.clone()),
+            location: None,
+            assumption: None,
+            pod_id: None,
+            mode: Mode::Pages,
+            short: false,
+        };
+
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--nocache" => options.use_cache = false,
+                "--units" => {
+                    let units = take_quoted(&mut args)
+                        .ok_or_else(|| "Please specify either metric or imperial units.")?;
+                    options.units = Some(parse_units(&units)?.to_owned());
+                },
+                "--location" => {
+                    options.location = Some(take_quoted(&mut args)
+                        .ok_or_else(|| "Please specify a location.")?);
+                },
+                "--pod" => {
+                    // Titles containing spaces must be quoted.
+                    let title = take_quoted(&mut args)
+                        .ok_or_else(|| "Please specify a pod title.")?;
+                    options.pod_id = Some(pod_id(&title));
+                },
+                "--text" => options.mode = Mode::Text,
+                "--short" => options.short = true,
+                _ => rest.push(arg.clone()),
+            }
+        }
+
+        Ok((options, rest))
+    }
+
+    fn params(&self) -> HashMap<&str, &str> {
+        let mut params = HashMap::new();
+        if let Some(ref units) = self.units {
+            params.insert("units", units.as_str());
+        }
+        if let Some(ref location) = self.location {
+            params.insert("location", location.as_str());
+        }
+        if let Some(ref assumption) = self.assumption {
+            params.insert("assumption", assumption.as_str());
+        }
+        if let Some(ref pod_id) = self.pod_id {
+            params.insert("includepodid", pod_id.as_str());
+        }
+        if self.mode == Mode::Text {
+            params.insert("format", "plaintext");
+        }
+        params
+    }
+}
+
+// An alternative interpretation of a query, which the user may choose.
+#[derive(Debug)]
+enum Choice {
+    // An assumption, with its description and its input parameter.
+    Assumption(String, String),
+    // A "did you mean" suggestion, which replaces the query.
+    Query(String),
+}
+
+// A list of alternative interpretations, waiting for the user to choose one.
+#[derive(Debug)]
+struct PendingChoice {
+    query: String,
+    options: QueryOptions,
+    choices: Vec<Choice>,
+    created_at: Instant,
+}
+
+// Takes the next argument, joining it with the arguments which follow if it
+// begins a quoted string.
+fn take_quoted<I>(args: &mut I) -> Option<String>
+    where I: Iterator<Item = String>,
+{
+    let mut words = Vec::new();
+    while let Some(arg) = args.next() {
+        let done = words.is_empty() && !arg.starts_with('"') || arg.ends_with('"');
+        words.push(arg);
+        if done {
+            break;
+        }
+    }
+
+    match words.join(" ").trim_matches('"') {
+        "" => None,
+        quoted => Some(quoted.to_owned()),
+    }
+}
+
+// Converts user-facing unit names into the values used by the API.
+fn parse_units(units: &str) -> Result<&'static str, String> {
+    match units {
+        "metric" => Ok("metric"),
+        "imperial" | "nonmetric" => Ok("nonmetric"),
+        _ => Err(format!("Unknown units \"{}\". Please use either metric or imperial.", units)),
+    }
+}
+
+// Sets the default units used in the user's queries.
+fn units(context: &Context, message: &Message, args: &[String]) -> Result<(), String> {
+    let units = match args.first().map(String::as_ref) {
+        Some("default") => None,
+        Some(units) => Some(parse_units(units)?.to_owned()),
+        None => return Err("Please specify metric, imperial, or default units.".to_owned()),
+    };
+
+    let response = match units {
+        Some(ref units) if units == "metric" => "Your queries will now use metric units.",
+        Some(_) => "Your queries will now use imperial units.",
+        None => "Your queries will now use the default units.",
+    };
+
+    let mut settings = USER_SETTINGS.lock().expect("Failed to lock Wolfram|Alpha user settings");
+    settings.get_mut()
+        .entry(message.author.id.0.to_string())
+        .or_insert_with(UserSettings::default)
+        .units = units;
+    settings.save();
+
+    check_msg(context.say(response));
+
+    Ok(())
+}
+
+// Converts a pod title into its ID. Pod IDs are usually their titles in camel
+// case, e.g. "Decimal approximation" has the ID "DecimalApproximation".
+fn pod_id(title: &str) -> String {
+    title.split_whitespace()
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect::<String>()
+}
+
+// Runs a query and sends its results, paginated by pod.
+fn run_query(
+    context: &Context,
+    message: &Message,
+    query: &str,
+    options: &QueryOptions
+) -> Result<(), String> {
+    let plugin = plugin()?;
+    let caller = Caller::new(message);
+    let author = message.author.id;
+    let (job_query, job_options) = (query.to_owned(), options.clone());
+    let (query, options) = (query.to_owned(), options.clone());
+
+    job::run(context,
+             message,
+             "your query",
+             move || {
+                 plugin.query(&job_query,
+                           job_options.params(),
+                           job_options.use_cache,
+                           Some(&caller))
+                     .map_err(|err| suggest_calc(err, &job_query))
+             },
+             move |context, working, query_result| {
+                 show_result(context, working, author, &query, &options, query_result)
+                     .map_err(|err| suggest_calc(err, &query))
+             })
+}
+
+// Shows a query result in place of the "working..." message for the query.
+fn show_result(
+    context: &Context,
+    working: &Message,
+    author: UserId,
+    query: &str,
+    options: &QueryOptions,
+    query_result: QueryResult
+) -> Result<(), String> {
+    let colour = random_colour();
+    if query_result.success {
+        // Format the `QueryResult` into Discord-ready output.
+        let pods = query_result.pod
+            .ok_or_else(|| "Result did not contain any parsable information")?;
+        match options.mode {
+            Mode::Pages => show_pages(context, working, author, &pods, colour)?,
+            Mode::Text => job::edit_text(context, working, &format_text(&pods)?),
+        }
+
+        // Offer any alternative interpretations of the query. The first value
+        // of each assumption is the one which was used.
+        if let Some(assumptions) = query_result.assumptions {
+            let choices = assumptions.assumption
+                .iter()
+                .flat_map(|a| {
+                    let word = a.word.clone();
+                    a.value.iter().skip(1).map(move |v| {
+                        let description = match word {
+                            Some(ref word) => format!("\"{}\" as {}", word, v.desc),
+                            None => v.desc.clone(),
+                        };
+                        Choice::Assumption(description, v.input.clone())
+                    })
+                })
+                .take(MAX_CHOICES)
+                .collect::<Vec<Choice>>();
+            if !choices.is_empty() {
+                check_msg(context.send_message(working.channel_id, |m| {
+                    m.embed(|e| choices_embed(e, &choices, colour))
+                }));
+                remember_choices(working.channel_id, author, query, options, choices);
+            }
+        }
+    } else if let Some(didyoumeans) = query_result.didyoumeans {
+        let choices = didyoumeans.didyoumean
+            .iter()
+            .take(MAX_CHOICES)
+            .map(|item| Choice::Query(item.value.clone()))
+            .collect::<Vec<Choice>>();
+        if choices.is_empty() {
+            return Err("Query was unsuccessful. Perhaps try rewording it?".to_owned());
+        }
+        check_msg(context.edit_message(working.channel_id,
+                                       working.id,
+                                       "",
+                                       |e| choices_embed(e, &choices, colour)));
+        remember_choices(working.channel_id, author, query, options, choices);
+    } else if let Some(error) = query_result.error {
+        check_msg(context.edit_message(working.channel_id, working.id, "", |e| {
+            e.title("Wolfram|Alpha returned an error.")
+                .colour(colour)
+                .field(|f| {
+                    let field = f.name("Error");
+
+                    let description = format!(
+                        "Code: {}\nMessage: {}",
+                        error.code,
+                        error.msg,
+                    );
+                    field.value(description.as_str())
+                })
+        }));
+    } else {
+        return Err("Query was unsuccessful. Perhaps try rewording it?".to_owned());
+    }
+
+    Ok(())
+}
+
+// Lists alternative interpretations of a query in an embed.
+fn choices_embed(embed: CreateEmbed, choices: &[Choice], colour: Colour) -> CreateEmbed {
+    let title = match choices[0] {
+        Choice::Assumption(..) => "Other interpretations",
+        Choice::Query(_) => "Query unsuccessful. Did you mean:",
+    };
+    let mut description = String::new();
+    for (i, choice) in choices.iter().enumerate() {
+        let text = match *choice {
+            Choice::Assumption(ref description, _) => description,
+            Choice::Query(ref query) => query,
         };
+        description.push_str(&format!("{}. {}\n", i + 1, text));
+    }
+
+    embed.title(title)
+        .colour(colour)
+        .description(&description)
+        .footer(|f| f.text("Reply with a number to choose."))
+}
+
+// Remembers the alternative interpretations offered to a user, so that they
+// can pick one by replying with its number.
+fn remember_choices(
+    channel_id: ChannelId,
+    author: UserId,
+    query: &str,
+    options: &QueryOptions,
+    choices: Vec<Choice>
+) {
+    let mut pending = PENDING.lock().expect("Failed to lock pending choices");
+    pending.insert((channel_id, author),
+                   PendingChoice {
+                       query: query.to_owned(),
+                       options: options.clone(),
+                       choices: choices,
+                       created_at: Instant::now(),
+                   });
+}
+
+// Formats the plaintext of every pod in a result as a single code block.
+fn format_text(pods: &[Pod]) -> Result<String, String> {
+    let mut text = String::new();
+    for pod in pods {
+        let lines = pod.subpod
+            .iter()
+            .filter_map(|subpod| subpod.plaintext.as_ref())
+            .filter(|plaintext| !plaintext.trim().is_empty())
+            // Break up anything which would end the code block early.
+            .map(|plaintext| plaintext.replace("```", "`\u{200b}``"))
+            .collect::<Vec<String>>();
+        if lines.is_empty() {
+            continue;
+        }
+
+        text.push_str(&format!("{}:\n", pod.title));
+        for line in lines.iter().flat_map(|plaintext| plaintext.lines()) {
+            text.push_str(&format!("  {}\n", line));
+        }
+    }
+    if text.is_empty() {
+        return Err("Result did not contain any parsable information".to_owned());
+    }
+
+    // Leave room for the code block delimiters within Discord's message limit.
+    if text.len() > 1900 {
+        let truncation_msg = "... (output too long)";
+        let mut end = 1900 - truncation_msg.len();
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str(truncation_msg);
+    }
+
+    Ok(format!("```\n{}```", text))
+}
+
+// Shows the pods of a result as pages, which can be navigated with reactions,
+// in place of the "working..." message for the query.
+fn show_pages(
+    context: &Context,
+    working: &Message,
+    author: UserId,
+    pods: &[Pod],
+    colour: Colour
+) -> Result<(), String> {
+    // The first pod is usually the interpretation of the input, which is shown
+    // on every page rather than on a page of its own.
+    let (interpretation, pods) = match pods.split_first() {
+        Some((first, rest)) if first.id == "Input" && !rest.is_empty() => {
+            (Page::new(first).text.trim().to_owned(), rest)
+        },
+        _ => (String::new(), pods),
+    };
+    if pods.is_empty() {
+        return Err("Result did not contain any parsable information".to_owned());
+    }
+
+    let pagination = Pagination {
+        author: author,
+        colour: colour,
+        created_at: Instant::now(),
+        interpretation: interpretation,
+        page: 0,
+        pages: pods.iter().map(Page::new).collect(),
+        removed_reactions: HashSet::new(),
+    };
+
+    context.edit_message(working.channel_id,
+                      working.id,
+                      "",
+                      |e| pagination.page_embed(e))
+        .map_err(stringify)?;
+    if pagination.pages.len() < 2 {
+        return Ok(());
+    }
 
-        result.push_str(&format!("{}\n", &text));
+    for emoji in &[PREVIOUS_PAGE, NEXT_PAGE] {
+        let _ = context.create_reaction(working.channel_id,
+                                        working.id,
+                                        ReactionType::Unicode((*emoji).to_owned()));
     }
 
-    // If the output is too long to fit in a single field, truncate it.
-    if result.len() > 1024 {
-        let truncation_msg = "... (output too long)".to_owned();
-        result.truncate(1024 - truncation_msg.len());
-        result.push_str(truncation_msg.as_str());
+    let mut paginations = PAGINATIONS.lock().expect("Failed to lock paginations");
+    // Stop tracking old results, so that they do not accumulate forever.
+    let expired = paginations.iter()
+        .filter(|&(_, p)| p.created_at.elapsed() > StdDuration::from_secs(PAGINATION_TIMEOUT))
+        .map(|(id, _)| *id)
+        .collect::<Vec<MessageId>>();
+    for id in expired {
+        paginations.remove(&id);
     }
+    paginations.insert(working.id, pagination);
 
-    f.value(result.as_ref())
+    Ok(())
+}
+
+// Answers a query with a single line of text from the Short Answers API.
+fn short(
+    context: &Context,
+    message: &Message,
+    query: &str,
+    options: QueryOptions
+) -> Result<(), String> {
+    let plugin = plugin()?;
+    let caller = Caller::new(message);
+    let query = query.to_owned();
+
+    job::run(context,
+             message,
+             "your query",
+             move || {
+                 plugin.short_answer(&query, options.params(), options.use_cache, Some(&caller))
+                     .map_err(|err| suggest_calc(err, &query))
+             },
+             |context, working, answer| {
+                 job::edit_text(context, working, &answer);
+                 Ok(())
+             })
+}
+
+fn cache(context: &Context, message: &Message, args: &[String]) -> Result<(), String> {
+    match args.first().map(String::as_ref) {
+        Some("purge") => {
+            if !CONFIG.owners.contains(&message.author.id.0) {
+                return Err("You do not have permission to do that.".to_owned());
+            }
+
+            let count = plugin()?.purge_cache();
+            check_msg(context.say(&format!("Purged {} cached Wolfram|Alpha results.", count)));
+        },
+        _ => {
+            let (hits, misses) = cache_stats();
+            check_msg(context.say(&format!("Cache hits: {}\nCache misses: {}", hits, misses)));
+        },
+    }
+
+    Ok(())
 }
 
 #[inline]
@@ -200,3 +1092,166 @@ fn unescape(s: &str) -> String {
         .replace("&quot;", "\"")
         .replace("&apos;", "\'")
 }
+
+#[cfg(test)]
+mod tests {
+    use fixture::FixtureServer;
+    use std::collections::{HashMap, HashSet};
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use super::{Page, WolframPlugin, format_text, pod_id, subcommand};
+
+    // Returns a plugin which sends its requests to the fixture server, with a
+    // fresh cache of its own.
+    fn plugin(server: &FixtureServer, name: &str) -> WolframPlugin {
+        let cache_file = env::temp_dir().join(format!("smexybot-wolfram-{}.json", name));
+        let _ = fs::remove_file(&cache_file);
+        WolframPlugin::with_urls("test-app-id".to_owned(),
+                                 server.url().to_owned(),
+                                 format!("{}result", server.url()),
+                                 cache_file.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_query_success() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/wolfram/success.xml"));
+        let plugin = plugin(&server, "success");
+
+        let result = plugin.query("2 + 2", HashMap::new(), false, None).unwrap();
+        assert!(result.success);
+        let pods = result.pod.unwrap();
+        assert_eq!(pods.len(), 2);
+        assert_eq!(Page::new(&pods[1]).title, "Result");
+        assert_eq!(Page::new(&pods[1]).text, "4\n");
+        assert_eq!(format_text(&pods).unwrap(), "```\nInput:\n  2 + 2\nResult:\n  4\n```");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("/query?"));
+        assert!(requests[0].contains("appid=test-app-id"));
+        assert!(requests[0].contains("input=2+%2B+2"));
+    }
+
+    #[test]
+    fn test_query_cached() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/wolfram/success.xml"));
+        let plugin = plugin(&server, "cached");
+
+        assert!(plugin.query("2 + 2", HashMap::new(), true, None).unwrap().success);
+        // Queries differing only in whitespace share a cache entry.
+        assert!(plugin.query("2  +  2 ", HashMap::new(), true, None).unwrap().success);
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(plugin.cache_hits.load(Ordering::Relaxed), 1);
+
+        // The cache is bypassed on request.
+        assert!(plugin.query("2 + 2", HashMap::new(), false, None).unwrap().success);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_query_did_you_mean() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/wolfram/didyoumean.xml"));
+        let plugin = plugin(&server, "didyoumean");
+
+        let result = plugin.query("frnace", HashMap::new(), true, None).unwrap();
+        assert!(!result.success);
+        let didyoumeans = result.didyoumeans.unwrap().didyoumean;
+        assert_eq!(didyoumeans.len(), 1);
+        assert_eq!(didyoumeans[0].value, "france");
+
+        // Unsuccessful results are not cached.
+        plugin.query("frnace", HashMap::new(), true, None).unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_query_error() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/wolfram/error.xml"));
+        let plugin = plugin(&server, "error");
+
+        let result = plugin.query("2 + 2", HashMap::new(), false, None).unwrap();
+        assert!(!result.success);
+        let error = result.error.unwrap();
+        assert_eq!(error.code.to_string(), "1");
+        assert_eq!(error.msg.to_string(), "Invalid appid");
+    }
+
+    #[test]
+    fn test_query_empty_pods() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/wolfram/empty.xml"));
+        let plugin = plugin(&server, "empty");
+
+        let result = plugin.query("2 + 2", HashMap::new(), false, None).unwrap();
+        assert!(result.success);
+        let pods = result.pod.unwrap_or_else(Vec::new);
+        assert!(pods.is_empty());
+        assert!(format_text(&pods).is_err());
+    }
+
+    #[test]
+    fn test_query_malformed_xml() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/wolfram/malformed.xml"));
+        let plugin = plugin(&server, "malformed");
+
+        let err = plugin.query("2 + 2", HashMap::new(), false, None).unwrap_err();
+        assert_eq!(err, "Failed to query WolframAlpha: failed to parse response");
+    }
+
+    #[test]
+    fn test_short_answer() {
+        let server = FixtureServer::new("200 OK", "4");
+        let plugin = plugin(&server, "short");
+
+        let mut params = HashMap::new();
+        params.insert("units", "nonmetric");
+        params.insert("assumption", "*C.pi-_*Movie-");
+        assert_eq!(plugin.short_answer("2 + 2", params, false, None).unwrap(), "4");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("/result?"));
+        assert!(requests[0].contains("i=2+%2B+2"));
+        // Units are renamed, and parameters unsupported by the Short Answers
+        // API are dropped.
+        assert!(requests[0].contains("units=imperial"));
+        assert!(!requests[0].contains("assumption"));
+    }
+
+    #[test]
+    fn test_short_answer_unavailable() {
+        let server = FixtureServer::new("501 Not Implemented", "No short answer available");
+        let plugin = plugin(&server, "short-unavailable");
+
+        let err = plugin.short_answer("integrate x", HashMap::new(), true, None).unwrap_err();
+        assert!(err.starts_with("Wolfram|Alpha has no short answer for that query."));
+    }
+
+    #[test]
+    fn test_subcommand() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<String>>();
+
+        assert_eq!(subcommand(&args("cache")), Some("cache"));
+        assert_eq!(subcommand(&args("cache purge")), Some("cache"));
+        assert_eq!(subcommand(&args("units imperial")), Some("units"));
+        assert_eq!(subcommand(&args("units default")), Some("units"));
+        // Queries starting with the name of a subcommand are left alone.
+        assert_eq!(subcommand(&args("units of force")), None);
+        assert_eq!(subcommand(&args("units furlongs")), None);
+        assert_eq!(subcommand(&args("cache memory size")), None);
+        assert_eq!(subcommand(&args("short story")), None);
+        assert_eq!(subcommand(&[]), None);
+    }
+
+    #[test]
+    fn test_pod_id() {
+        assert_eq!(pod_id("Decimal approximation"), "DecimalApproximation");
+        assert_eq!(pod_id("Result"), "Result");
+    }
+}
@@ -1,9 +1,15 @@
-#[derive(Debug, Deserialize)]
-struct CseResponse {
-    pub items: Vec<CseItem>,
-}
-
-#[derive(Debug, Deserialize)]
-struct CseItem {
-    pub link: String,
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Settings {
+    /// The IDs of the channels in which comics are shown as plain links.
+    compact: HashSet<String>,
+    /// The IDs of the channels to which new comics are posted.
+    #[serde(default)]
+    subscriptions: HashSet<String>,
+    /// The days on which a random comic was last posted, as `YYYY-MM-DD`, by
+    /// the ID of the channel to which it is posted daily.
+    #[serde(default)]
+    daily: HashMap<String, String>,
+    /// The number of the last comic posted to subscribed channels.
+    #[serde(default)]
+    last_posted: Option<u32>,
 }
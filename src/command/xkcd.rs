@@ -8,32 +8,42 @@
 // except according to those terms.
 
 //! Provides a command which allows a user to request and view XKCD comics.
+//!
+//! Comics are shown as embeds with their title, alt text and publication
+//! date, unless a channel has opted to show them as plain links instead.
+//!
+//! Channels may also subscribe to have new comics, or a random comic each day,
+//! posted to them automatically.
 
-extern crate regex;
-extern crate xkcd;
-
-use error::{Error, Result};
-use hyper::Url;
+use ::CONFIG;
+use chrono::UTC;
+use command::quota::{Caller, Service};
+use command::xkcd_index::{self, INDEX_NOT_READY, IndexedComic, MISSING_COMIC};
+use command::xkcd_search::{self, SearchProvider};
 use hyper::client::Client;
-use self::regex::Regex;
-use serde_json;
-use std::env;
-use std::io::Read;
-use util::{check_msg, split_list};
+use job;
+use rand::{self, Rng};
+use serenity::client::Context;
+use serenity::model::{ChannelId, Message};
+use serenity::utils::builder::CreateEmbed;
+use std::collections::{HashMap, HashSet};
+use std::result::Result as StdResult;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use store::Store;
+use util::{check_msg, is_admin, random_colour, split_list};
 
 lazy_static! {
-    static ref GOOGLE_CSE_URL: Url = "https://www.googleapis.com/customsearch/v1".parse::<Url>()
-        .unwrap();
-    static ref PLUGIN: XkcdPlugin = {
-        let cse_api_key = env::var("GOOGLE_XKCD_CUSTOM_SEARCH_API_KEY")
-            .expect("GOOGLE_XKCD_CUSTOM_SEARCH_API_KEY env var not set.");
-        let cse_engine_id = env::var("GOOGLE_XKCD_CUSTOM_SEARCH_ENGINE_ID")
-            .expect("GOOGLE_XKCD_CUSTOM_SEARCH_ENGINE_ID env var not set.");
-        XkcdPlugin::new(cse_api_key, cse_engine_id)
-    };
-    static ref XKCD_URL_REGEX: Regex = Regex::new(r"^https://xkcd.com/(\d*)").unwrap();
+    static ref PLUGIN: XkcdPlugin = XkcdPlugin::new(xkcd_search::load_provider());
+    static ref SETTINGS: Mutex<Store<Settings>> = Mutex::new(Store::new("xkcd.json"));
+    // The number of the comic most recently shown in each channel.
+    static ref LAST_SHOWN: Mutex<HashMap<ChannelId, u32>> = Mutex::new(HashMap::new());
 }
 
+// The maximum number of comics which may be listed at once.
+const MAX_RANGE: u32 = 10;
+
 #[cfg(feature = "nightly")]
 include!("xkcd.in.rs");
 
@@ -42,113 +52,474 @@ include!(concat!(env!("OUT_DIR"), "/xkcd.rs"));
 
 struct XkcdPlugin {
     hyper_client: Client,
-    google_custom_search_api_key: String,
-    google_custom_search_engine_id: String,
+    // The provider used to search for comics, or an explanation of why it is
+    // unavailable.
+    search_provider: StdResult<Box<SearchProvider>, String>,
 }
 
 impl XkcdPlugin {
-    /// Returns a new instance of `XkcdPlugin`.
-    fn new(google_custom_search_api_key: String, google_custom_search_engine_id: String) -> Self {
+    /// Returns a new instance of `XkcdPlugin`. Searching is disabled if the
+    /// search provider is unavailable.
+    fn new(search_provider: StdResult<Box<SearchProvider>, String>) -> Self {
         XkcdPlugin {
             hyper_client: Client::new(),
-            google_custom_search_api_key: google_custom_search_api_key,
-            google_custom_search_engine_id: google_custom_search_engine_id,
+            search_provider: search_provider,
         }
     }
 
-    fn random(&self) -> String {
+    fn random(&self) -> StdResult<IndexedComic, String> {
         debug!("Searching for random comic");
-        xkcd::random::random(&self.hyper_client)
-            .ok()
-            .map(|comic| comic.img.into_string())
-            .unwrap_or("Failed to retrieve random comic".to_owned())
+        let latest = self.latest_comic()?;
+        let mut rng = rand::thread_rng();
+        let mut num = MISSING_COMIC;
+        while num == MISSING_COMIC {
+            num = rng.gen_range(1, latest.num + 1);
+        }
+
+        if num == latest.num {
+            Ok(latest)
+        } else {
+            self.comic(num)
+        }
     }
 
-    fn search(&self, args: &[String]) -> String {
+    fn search(&self, caller: &Caller, args: &[String]) -> StdResult<IndexedComic, String> {
         debug!("Searching for comic");
         let query: String = match args.len() {
-            0 => return "Missing comic search query".to_owned(),
+            0 => return Err("Missing comic search query".to_owned()),
             _ => args.join(" "),
         };
         trace!("Query: {}", query);
 
-        match query_cse(&self.hyper_client,
-                        &query,
-                        &self.google_custom_search_api_key,
-                        &self.google_custom_search_engine_id) {
-            Ok(res) => {
-                let mut comic_urls = res.items
-                    .iter()
-                    .filter_map(|item| XKCD_URL_REGEX.captures_iter(&item.link).next())
-                    .filter_map(|capture| capture.at(1));
-                match comic_urls.next() {
-                    Some(comic_id_str) => comic_id_str.parse::<u32>()
-                        .ok()
-                        .map(|id| {
-                            xkcd::comics::get(&self.hyper_client, id)
-                                .ok()
-                                .map(|comic| comic.img.into_string())
-                                .unwrap_or_else(|| format!("Failed to retrieve comic: {}", id))
-                        })
-                        .unwrap_or_else(|| format!("Failed to retrieve comic: {}", comic_id_str)),
-                    None => "No results in query".to_owned(),
-                }
-            },
-            Err(_) => "No matching comic found".to_owned(),
+        let provider = self.search_provider.as_ref().map_err(|err| err.clone())?;
+        let num = provider.search(&self.hyper_client, caller, &query)?;
+        self.comic(num)
+    }
+
+    // Retrieves a comic, from the local index if possible.
+    fn comic(&self, id: u32) -> StdResult<IndexedComic, String> {
+        if let Some(comic) = xkcd_index::get(id) {
+            return Ok(comic);
         }
+
+        xkcd_index::fetch(&self.hyper_client, Some(id))
+            .map_err(|_| format!("Failed to retrieve comic: {}", id))
     }
 
-    fn latest_comic(&self) -> String {
-        debug!("Retrieving latest comic");
-        match xkcd::comics::latest(&self.hyper_client) {
-            Ok(comic) => comic.img.into_string(),
-            Err(_) => "Failed to retrieve latest comic".to_owned(),
+    // Retrieves the comic after the specified one, checking the local index
+    // before asking for the latest comic.
+    fn next(&self, num: u32) -> StdResult<IndexedComic, String> {
+        let next = next_number(num);
+        if let Some(comic) = xkcd_index::get(next) {
+            return Ok(comic);
+        }
+
+        let latest = self.latest_comic()?;
+        if next > latest.num {
+            Err(format!("#{} is the latest comic.", num))
+        } else if next == latest.num {
+            Ok(latest)
+        } else {
+            self.comic(next)
+        }
+    }
+
+    // Retrieves the comic before the specified one.
+    fn previous(&self, num: u32) -> StdResult<IndexedComic, String> {
+        match previous_number(num) {
+            Some(previous) => self.comic(previous),
+            None => Err(format!("#{} is the first comic.", num)),
         }
     }
+
+    fn latest_comic(&self) -> StdResult<IndexedComic, String> {
+        debug!("Retrieving latest comic");
+        xkcd_index::fetch(&self.hyper_client, None)
+            .map_err(|_| "Failed to retrieve latest comic".to_owned())
+    }
 }
 
-command!(xkcd(context, _message, args) {
+command!(xkcd(context, message, args) {
     let (command, args) = split_list(args);
 
-    let response = match command.as_ref().map(String::as_ref) {
-        Some("search") => PLUGIN.search(&args),
-        Some("random") => PLUGIN.random(),
+    let comic = match command.as_ref().map(String::as_ref) {
+        Some("search") => {
+            let caller = Caller::new(message);
+            return job::run(context,
+                            message,
+                            "your search",
+                            move || PLUGIN.search(&caller, &args),
+                            |context, working, comic| {
+                                remember_shown(working.channel_id, comic.num);
+                                if is_compact(working.channel_id) {
+                                    job::edit_text(context, working, &comic.img);
+                                } else {
+                                    check_msg(context.edit_message(working.channel_id,
+                                                                   working.id,
+                                                                   "",
+                                                                   |e| comic_embed(e, &comic)));
+                                }
+                                Ok(())
+                            });
+        },
+        Some("compact") => return compact(context, message, &args),
+        Some("subscribe") => return subscribe(context, message, &args, true),
+        Some("unsubscribe") => return subscribe(context, message, &args, false),
+        Some("random") => PLUGIN.random()?,
+        Some("first") => PLUGIN.comic(1)?,
+        Some("next") => PLUGIN.next(last_shown(message.channel_id)?)?,
+        Some("prev") => PLUGIN.previous(last_shown(message.channel_id)?)?,
+        Some("range") => return range(context, message, &args),
         Some(comic_id) => {
             match comic_id.parse() {
-                Ok(comic_id) => {
-                    match xkcd::comics::get(&PLUGIN.hyper_client, comic_id) {
-                        Ok(comic) => comic.img.into_string(),
-                        Err(_) => format!("Failed to retrieve comic: {}", comic_id),
+                Ok(comic_id) => PLUGIN.comic(comic_id)?,
+                // A quoted argument is the exact title of a comic.
+                Err(_) if comic_id.starts_with('"') => {
+                    let mut title = comic_id.to_owned();
+                    for arg in &args {
+                        title.push(' ');
+                        title.push_str(arg);
                     }
+                    find_by_title(title.trim_matches('"'))?
                 },
                 _ => {
-                    "Please provide a valid argument (\"search\", \"random\", or a comic ID)"
-                        .to_owned()
+                    return Err("Please provide a valid argument (\"search\", \"random\", \
+                                \"first\", \"next\", \"prev\", \"range\", \"compact\", \
+                                \"subscribe\", \"unsubscribe\", a comic ID, or a quoted \
+                                comic title)"
+                        .to_owned())
                 },
             }
         },
-        _ => PLUGIN.latest_comic(),
+        _ => PLUGIN.latest_comic()?,
     };
 
-    check_msg(context.say(response.as_ref()));
+    show_comic(message.channel_id, &comic);
 });
 
-fn query_cse(
-    client: &Client,
-    query: &str,
-    search_api_key: &str,
-    search_engine_id: &str
-) -> Result<CseResponse> {
-    let mut url = GOOGLE_CSE_URL.clone();
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("key", search_api_key)
-        .append_pair("cx", search_engine_id)
-        .append_pair("q", query);
-
-    let mut response = try!(client.get(url).send().map_err(Error::from));
-    let mut result = String::new();
-    try!(response.read_to_string(&mut result).map_err(Error::from));
-
-    serde_json::from_str(&result).map_err(Error::from)
+/// Starts the background task which posts new comics to subscribed channels,
+/// and random comics to channels with a daily subscription.
+pub fn start_poller() {
+    thread::spawn(|| loop {
+        if let Err(err) = post_new_comics() {
+            warn!("Failed to post new xkcd comics: {}", err);
+        }
+        if let Err(err) = post_daily_comics() {
+            warn!("Failed to post daily xkcd comics: {}", err);
+        }
+        thread::sleep(Duration::from_secs(CONFIG.xkcd.poll_interval));
+    });
+}
+
+// Posts any comics published since the last poll to the subscribed channels.
+fn post_new_comics() -> StdResult<(), String> {
+    let latest = PLUGIN.latest_comic()?;
+    let last_posted = SETTINGS.lock().expect("Failed to lock xkcd settings").get().last_posted;
+    let last_posted = match last_posted {
+        Some(last_posted) => last_posted,
+        // Don't post anything on the first poll, as the latest comic is not
+        // new to anyone.
+        None => {
+            mark_posted(latest.num);
+            return Ok(());
+        },
+    };
+
+    for num in (last_posted + 1)..(latest.num + 1) {
+        if num == MISSING_COMIC {
+            continue;
+        }
+        let comic = if num == latest.num {
+            latest.clone()
+        } else {
+            PLUGIN.comic(num)?
+        };
+
+        // The comic is marked as posted before posting it, so that it is never
+        // posted twice, even if the bot is restarted partway through.
+        mark_posted(num);
+        let channels = SETTINGS.lock()
+            .expect("Failed to lock xkcd settings")
+            .get()
+            .subscriptions
+            .clone();
+        for channel in channels {
+            if let Ok(channel) = channel.parse::<u64>() {
+                show_comic(ChannelId(channel), &comic);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Posts a random comic to each channel with a daily subscription, if one has
+// not yet been posted there today.
+fn post_daily_comics() -> StdResult<(), String> {
+    let today = UTC::today().format("%Y-%m-%d").to_string();
+    let channels = SETTINGS.lock()
+        .expect("Failed to lock xkcd settings")
+        .get()
+        .daily
+        .iter()
+        .filter(|&(_, day)| *day != today)
+        .map(|(channel, _)| channel.clone())
+        .collect::<Vec<String>>();
+
+    for channel in channels {
+        let comic = PLUGIN.random()?;
+        {
+            let mut settings = SETTINGS.lock().expect("Failed to lock xkcd settings");
+            settings.get_mut().daily.insert(channel.clone(), today.clone());
+            settings.save();
+        }
+        if let Ok(channel) = channel.parse::<u64>() {
+            show_comic(ChannelId(channel), &comic);
+        }
+    }
+
+    Ok(())
+}
+
+// Returns the number of the comic after the specified one, skipping #404.
+fn next_number(num: u32) -> u32 {
+    match num + 1 {
+        MISSING_COMIC => MISSING_COMIC + 1,
+        next => next,
+    }
+}
+
+// Returns the number of the comic before the specified one, skipping #404, or
+// `None` if it is the first comic.
+fn previous_number(num: u32) -> Option<u32> {
+    match num.saturating_sub(1) {
+        0 => None,
+        MISSING_COMIC => Some(MISSING_COMIC - 1),
+        previous => Some(previous),
+    }
+}
+
+// Records the number of the last comic posted to subscribed channels.
+fn mark_posted(num: u32) {
+    let mut settings = SETTINGS.lock().expect("Failed to lock xkcd settings");
+    settings.get_mut().last_posted = Some(num);
+    settings.save();
+}
+
+// Posts a comic to a channel, as an embed unless the channel prefers plain
+// links.
+fn show_comic(channel_id: ChannelId, comic: &IndexedComic) {
+    remember_shown(channel_id, comic.num);
+    if is_compact(channel_id) {
+        check_msg(channel_id.say(&comic.img));
+    } else {
+        check_msg(channel_id.send_message(|m| m.embed(|e| comic_embed(e, comic))));
+    }
+}
+
+// Records the comic most recently shown in a channel, which `xkcd next` and
+// `xkcd prev` are relative to.
+fn remember_shown(channel_id: ChannelId, num: u32) {
+    LAST_SHOWN.lock().expect("Failed to lock last shown comics").insert(channel_id, num);
+}
+
+fn last_shown(channel_id: ChannelId) -> StdResult<u32, String> {
+    LAST_SHOWN.lock()
+        .expect("Failed to lock last shown comics")
+        .get(&channel_id)
+        .cloned()
+        .ok_or_else(|| "No comic has been shown in this channel yet.".to_owned())
+}
+
+// Finds a comic by its exact title, ignoring case.
+fn find_by_title(title: &str) -> StdResult<IndexedComic, String> {
+    if xkcd_index::len() == 0 {
+        return Err(INDEX_NOT_READY.to_owned());
+    }
+
+    xkcd_index::find_by_title(title)
+        .ok_or_else(|| format!("No comic is titled \"{}\".", title))
+}
+
+// Lists the titles of and links to a range of comics, e.g. `100-105`.
+fn range(context: &Context, message: &Message, args: &[String]) -> StdResult<(), String> {
+    let (start, end) = parse_range(args.first().map(String::as_str))?;
+
+    let mut response = String::new();
+    let mut last = None;
+    for num in start..(end + 1) {
+        if num == MISSING_COMIC {
+            continue;
+        }
+        let comic = PLUGIN.comic(num)?;
+        // Angle brackets stop Discord from embedding every link.
+        response.push_str(&format!("#{}: {} <https://xkcd.com/{}/>\n",
+                                   comic.num,
+                                   comic.safe_title,
+                                   comic.num));
+        last = Some(comic.num);
+    }
+    if let Some(last) = last {
+        remember_shown(message.channel_id, last);
+    }
+    check_msg(context.say(&response));
+
+    Ok(())
+}
+
+// Parses a range of comic numbers, e.g. `100-105`, into its inclusive bounds.
+fn parse_range(range: Option<&str>) -> StdResult<(u32, u32), String> {
+    let bounds = range.and_then(|range| {
+        let mut bounds = range.splitn(2, '-').map(|bound| bound.trim().parse::<u32>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(start)), Some(Ok(end))) if start > 0 && start <= end => Some((start, end)),
+            _ => None,
+        }
+    });
+    let (start, end) = match bounds {
+        Some(bounds) => bounds,
+        None => return Err("Please specify a range of comics, e.g. `100-105`.".to_owned()),
+    };
+    if end - start >= MAX_RANGE {
+        return Err(format!("At most {} comics may be listed at once.", MAX_RANGE));
+    }
+
+    Ok((start, end))
+}
+
+// Describes a comic in an embed, with its alt text in the footer.
+fn comic_embed(embed: CreateEmbed, comic: &IndexedComic) -> CreateEmbed {
+    embed.title(&format!("#{}: {}", comic.num, comic.safe_title))
+        .url(&format!("https://xkcd.com/{}/", comic.num))
+        .colour(random_colour())
+        .description(&format!("Published {}", comic.date))
+        .image(&comic.img)
+        .footer(|f| f.text(&comic.alt))
+}
+
+// Returns whether comics are shown as plain links in a channel, rather than
+// as embeds.
+fn is_compact(channel_id: ChannelId) -> bool {
+    SETTINGS.lock()
+        .expect("Failed to lock xkcd settings")
+        .get()
+        .compact
+        .contains(&channel_id.to_string())
+}
+
+fn compact(context: &Context, message: &Message, args: &[String]) -> StdResult<(), String> {
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    let channel = message.channel_id.to_string();
+    let mut settings = SETTINGS.lock().expect("Failed to lock xkcd settings");
+    let response = match args.first().map(String::as_ref) {
+        Some("on") => {
+            settings.get_mut().compact.insert(channel);
+            "Comics will now be shown as plain links in this channel."
+        },
+        Some("off") => {
+            settings.get_mut().compact.remove(&channel);
+            "Comics will now be shown as embeds in this channel."
+        },
+        _ => return Err("Please specify \"on\" or \"off\".".to_owned()),
+    };
+    settings.save();
+    check_msg(context.say(response));
+
+    Ok(())
+}
+
+// Subscribes or unsubscribes a channel to new comics, or to a daily random
+// comic if "daily" is specified.
+fn subscribe(
+    context: &Context,
+    message: &Message,
+    args: &[String],
+    enable: bool
+) -> StdResult<(), String> {
+    if !is_admin(message) {
+        return Err("You do not have permission to do that.".to_owned());
+    }
+
+    let daily = match args.first().map(String::as_ref) {
+        Some("daily") => true,
+        None => false,
+        Some(_) => return Err("Please specify \"daily\" or nothing.".to_owned()),
+    };
+
+    let channel = message.channel_id.to_string();
+    let mut settings = SETTINGS.lock().expect("Failed to lock xkcd settings");
+    let response = match (daily, enable) {
+        (false, true) => {
+            settings.get_mut().subscriptions.insert(channel);
+            "New comics will now be posted in this channel."
+        },
+        (false, false) => {
+            settings.get_mut().subscriptions.remove(&channel);
+            "New comics will no longer be posted in this channel."
+        },
+        (true, true) => {
+            settings.get_mut().daily.entry(channel).or_insert_with(String::new);
+            "A random comic will now be posted in this channel every day."
+        },
+        (true, false) => {
+            settings.get_mut().daily.remove(&channel);
+            "A random comic will no longer be posted in this channel every day."
+        },
+    };
+    settings.save();
+    check_msg(context.say(response));
+
+    Ok(())
+}
+
+/// Returns the service whose quota comic searches count against, if any.
+pub fn search_quota() -> Option<Service> {
+    PLUGIN.search_provider.as_ref().ok().and_then(|provider| provider.quota_service())
+}
+
+/// Returns an explanation of why comic searches are unavailable, if they are.
+pub fn search_unavailable() -> Option<&'static str> {
+    PLUGIN.search_provider.as_ref().err().map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_number, parse_range, previous_number};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range(Some("100-105")), Ok((100, 105)));
+        assert_eq!(parse_range(Some("7 - 7")), Ok((7, 7)));
+        assert_eq!(parse_range(Some("1-10")), Ok((1, 10)));
+    }
+
+    #[test]
+    fn test_parse_range_errors() {
+        let invalid = Err("Please specify a range of comics, e.g. `100-105`.".to_owned());
+        assert_eq!(parse_range(None), invalid);
+        assert_eq!(parse_range(Some("100")), invalid);
+        assert_eq!(parse_range(Some("105-100")), invalid);
+        assert_eq!(parse_range(Some("0-5")), invalid);
+        assert_eq!(parse_range(Some("a-b")), invalid);
+        assert_eq!(parse_range(Some("1-11")),
+                   Err("At most 10 comics may be listed at once.".to_owned()));
+    }
+
+    #[test]
+    fn test_next_number() {
+        assert_eq!(next_number(1), 2);
+        assert_eq!(next_number(403), 405);
+        assert_eq!(next_number(404), 405);
+    }
+
+    #[test]
+    fn test_previous_number() {
+        assert_eq!(previous_number(2), Some(1));
+        assert_eq!(previous_number(405), Some(403));
+        assert_eq!(previous_number(1), None);
+        assert_eq!(previous_number(0), None);
+    }
 }
@@ -0,0 +1,24 @@
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ComicIndex {
+    // The indexed comics, in ascending order of number.
+    comics: Vec<IndexedComic>,
+}
+
+/// The metadata of a comic, as stored in the local index.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IndexedComic {
+    /// The number of the comic.
+    pub num: u32,
+    /// The title of the comic.
+    pub title: String,
+    /// The title of the comic, without any markup.
+    pub safe_title: String,
+    /// The alt (mouseover) text of the comic.
+    pub alt: String,
+    /// The transcript of the comic, which is empty for most recent comics.
+    pub transcript: String,
+    /// The publication date of the comic, as `YYYY-MM-DD`.
+    pub date: String,
+    /// The URL of the comic's image.
+    pub img: String,
+}
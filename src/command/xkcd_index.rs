@@ -0,0 +1,368 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Maintains a local index of xkcd comic metadata, which allows comics to be
+//! searched without an external search engine.
+//!
+//! A background task fetches the metadata of every comic on startup, and then
+//! periodically fetches any comics published since the last sync.
+
+extern crate xkcd;
+
+use ::CONFIG;
+use command::xkcd_search::get_json;
+use error::Result as ApiResult;
+use hyper::Url;
+use hyper::client::Client;
+use std::cmp::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use store::Store;
+
+lazy_static! {
+    // The index is rebuilt from the xkcd API if it cannot be read.
+    static ref INDEX: Mutex<Store<ComicIndex>> =
+        Mutex::new(Store::load_or_default("xkcd_index.json"));
+}
+
+// The number of comics to fetch between saves of the index, so that the
+// progress of the initial sync is not lost if the bot is restarted.
+const SAVE_INTERVAL: usize = 100;
+
+/// The explanation given when the index is needed but has not been built yet.
+pub const INDEX_NOT_READY: &'static str = "The comic index is still being built. Please try \
+                                           again in a few minutes.";
+
+/// The number of comic #404, which intentionally does not exist.
+pub const MISSING_COMIC: u32 = 404;
+
+// The weights of matches in each field of a comic when ranking results.
+const TITLE_WEIGHT: f64 = 3.0;
+const ALT_WEIGHT: f64 = 1.5;
+const TRANSCRIPT_WEIGHT: f64 = 1.0;
+
+// The bonus for a comic whose title contains the entire query.
+const TITLE_PHRASE_BONUS: f64 = 5.0;
+
+#[cfg(feature = "nightly")]
+include!("xkcd_index.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/xkcd_index.rs"));
+
+/// Starts the background task which keeps the index up to date.
+pub fn start_sync() {
+    thread::spawn(|| {
+        let client = Client::new();
+        loop {
+            match sync(&client) {
+                Ok(0) => debug!("xkcd index is up to date"),
+                Ok(added) => info!("Added {} comics to the xkcd index", added),
+                Err(err) => warn!("Failed to sync xkcd index: {}", err),
+            }
+            thread::sleep(Duration::from_secs(CONFIG.xkcd.sync_interval));
+        }
+    });
+}
+
+/// Returns the number of comics in the index.
+pub fn len() -> usize {
+    INDEX.lock().expect("Failed to lock xkcd index").get().comics.len()
+}
+
+/// Returns the indexed comic with the specified number, if any.
+pub fn get(num: u32) -> Option<IndexedComic> {
+    let index = INDEX.lock().expect("Failed to lock xkcd index");
+    let comics = &index.get().comics;
+    comics.binary_search_by_key(&num, |comic| comic.num).ok().map(|i| comics[i].clone())
+}
+
+/// Returns the indexed comic with the specified title, ignoring case, if any.
+pub fn find_by_title(title: &str) -> Option<IndexedComic> {
+    let index = INDEX.lock().expect("Failed to lock xkcd index");
+    find_title(&index.get().comics, title).cloned()
+}
+
+fn find_title<'a>(comics: &'a [IndexedComic], title: &str) -> Option<&'a IndexedComic> {
+    let title = title.trim().to_lowercase();
+    comics.iter()
+        .find(|comic| {
+            comic.title.to_lowercase() == title || comic.safe_title.to_lowercase() == title
+        })
+}
+
+/// Retrieves a comic from the xkcd API, or the latest comic if no number is
+/// specified.
+pub fn fetch(client: &Client, num: Option<u32>) -> ApiResult<IndexedComic> {
+    fetch_from(client, &CONFIG.xkcd.api_url, num)
+}
+
+fn fetch_from(client: &Client, api_url: &str, num: Option<u32>) -> ApiResult<IndexedComic> {
+    let path = match num {
+        Some(num) => format!("{}/info.0.json", num),
+        None => "info.0.json".to_owned(),
+    };
+    let url = Url::parse(api_url)?.join(&path)?;
+
+    get_json::<xkcd::model::XkcdResponse>(client, url).map(IndexedComic::from)
+}
+
+/// Searches the index for comics matching a query, returning at most `limit`
+/// of the best matches, best first.
+pub fn search(query: &str, limit: usize) -> Vec<IndexedComic> {
+    let index = INDEX.lock().expect("Failed to lock xkcd index");
+    rank(&index.get().comics, query).into_iter().take(limit).cloned().collect()
+}
+
+// Fetches any comics which are newer than the newest indexed comic, returning
+// the number of comics which were added.
+fn sync(client: &Client) -> Result<usize, String> {
+    let latest = fetch(client, None)
+        .map_err(|err| format!("failed to retrieve latest comic: {}", err))?;
+    let newest = INDEX.lock()
+        .expect("Failed to lock xkcd index")
+        .get()
+        .comics
+        .last()
+        .map_or(0, |comic| comic.num);
+    if newest >= latest.num {
+        return Ok(0);
+    }
+
+    let mut added = 0;
+    for num in (newest + 1)..latest.num {
+        if num == MISSING_COMIC {
+            continue;
+        }
+
+        // Stop at the first failure, so that the index has no gaps. The sync
+        // resumes from the same comic next time.
+        match fetch(client, Some(num)) {
+            Ok(comic) => add(comic, &mut added),
+            Err(err) => {
+                INDEX.lock().expect("Failed to lock xkcd index").save();
+                return Err(format!("failed to retrieve comic {}: {}", num, err));
+            },
+        }
+    }
+    add(latest, &mut added);
+    INDEX.lock().expect("Failed to lock xkcd index").save();
+
+    Ok(added)
+}
+
+// Adds a comic to the end of the index, saving the index every
+// `SAVE_INTERVAL` comics.
+fn add(comic: IndexedComic, added: &mut usize) {
+    let mut index = INDEX.lock().expect("Failed to lock xkcd index");
+    index.get_mut().comics.push(comic);
+    *added += 1;
+    if *added % SAVE_INTERVAL == 0 {
+        index.save();
+    }
+}
+
+impl From<xkcd::model::XkcdResponse> for IndexedComic {
+    fn from(comic: xkcd::model::XkcdResponse) -> Self {
+        let date = match (comic.month.parse::<u32>(), comic.day.parse::<u32>()) {
+            (Ok(month), Ok(day)) => format!("{}-{:02}-{:02}", comic.year, month, day),
+            _ => format!("{}-{}-{}", comic.year, comic.month, comic.day),
+        };
+
+        IndexedComic {
+            num: comic.num,
+            title: comic.title,
+            safe_title: comic.safe_title,
+            alt: comic.alt,
+            transcript: comic.transcript,
+            date: date,
+            img: comic.img.into_string(),
+        }
+    }
+}
+
+// Ranks the comics which match any of the words in a query, best first.
+//
+// Each comic is scored by summing, over the words of the query, the number of
+// times the word appears in each field of the comic (dampened, and weighted by
+// field), multiplied by the rarity of the word across all comics.
+fn rank<'a>(comics: &'a [IndexedComic], query: &str) -> Vec<&'a IndexedComic> {
+    let mut terms = Vec::new();
+    for word in tokenize(query) {
+        if !terms.contains(&word) {
+            terms.push(word);
+        }
+    }
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    // The weighted frequency of each term in each comic.
+    let frequencies = comics.iter()
+        .map(|comic| {
+            let mut frequencies = vec![0.0; terms.len()];
+            for &(text, weight) in &[(&comic.title, TITLE_WEIGHT),
+                                     (&comic.alt, ALT_WEIGHT),
+                                     (&comic.transcript, TRANSCRIPT_WEIGHT)] {
+                let mut counts = vec![0; terms.len()];
+                for word in tokenize(text) {
+                    if let Some(i) = terms.iter().position(|term| *term == word) {
+                        counts[i] += 1;
+                    }
+                }
+                for (frequency, &count) in frequencies.iter_mut().zip(&counts) {
+                    if count > 0 {
+                        *frequency += weight * (1.0 + (count as f64).ln());
+                    }
+                }
+            }
+            frequencies
+        })
+        .collect::<Vec<Vec<f64>>>();
+
+    // The inverse document frequency of each term.
+    let total = comics.len() as f64;
+    let rarities = (0..terms.len())
+        .map(|i| {
+            let matching = frequencies.iter().filter(|f| f[i] > 0.0).count() as f64;
+            (1.0 + total / (1.0 + matching)).ln()
+        })
+        .collect::<Vec<f64>>();
+
+    let phrase = terms.join(" ");
+    let mut scored = comics.iter()
+        .zip(&frequencies)
+        .filter_map(|(comic, frequencies)| {
+            let mut score = frequencies.iter()
+                .zip(&rarities)
+                .map(|(frequency, rarity)| frequency * rarity)
+                .sum::<f64>();
+            if score <= 0.0 {
+                return None;
+            }
+            if tokenize(&comic.title).join(" ").contains(&phrase) {
+                score += TITLE_PHRASE_BONUS;
+            }
+            Some((comic, score))
+        })
+        .collect::<Vec<(&IndexedComic, f64)>>();
+
+    // Prefer older comics when scores are tied.
+    scored.sort_by(|a, b| {
+        match b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal) {
+            Ordering::Equal => a.0.num.cmp(&b.0.num),
+            ordering => ordering,
+        }
+    });
+    scored.into_iter().map(|(comic, _)| comic).collect()
+}
+
+// Splits text into lowercase words, ignoring punctuation and markup.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use fixture::FixtureServer;
+    use hyper::client::Client;
+    use super::{IndexedComic, fetch_from, find_title, rank};
+
+    fn comic(num: u32, title: &str, alt: &str, transcript: &str) -> IndexedComic {
+        IndexedComic {
+            num: num,
+            title: title.to_owned(),
+            safe_title: title.to_owned(),
+            alt: alt.to_owned(),
+            transcript: transcript.to_owned(),
+            date: "2011-07-20".to_owned(),
+            img: format!("https://imgs.xkcd.com/comics/{}.png", num),
+        }
+    }
+
+    fn comics() -> Vec<IndexedComic> {
+        vec![comic(221, "Random Number", "RFC 1149.5 specifies 4 as the standard IEEE-vetted \
+                                          random number.", ""),
+             comic(927, "Standards", "Fortunately, the charging one has been solved now that \
+                                      we've all standardized on mini-USB.",
+                   "[[Two people talking.]] How standards proliferate"),
+             comic(936, "Password Strength", "To anyone who understands information theory \
+                                              and security...",
+                   "Four random common words")]
+    }
+
+    #[test]
+    fn test_rank_title_first() {
+        let comics = comics();
+        let ranked = rank(&comics, "random");
+        let nums = ranked.iter().map(|comic| comic.num).collect::<Vec<u32>>();
+        assert_eq!(nums, vec![221, 936]);
+    }
+
+    #[test]
+    fn test_rank_ignores_case_and_punctuation() {
+        let comics = comics();
+        let ranked = rank(&comics, "STANDARDS!");
+        assert_eq!(ranked[0].num, 927);
+    }
+
+    #[test]
+    fn test_rank_no_match() {
+        let comics = comics();
+        assert!(rank(&comics, "velociraptor").is_empty());
+        assert!(rank(&comics, "  ").is_empty());
+    }
+
+    #[test]
+    fn test_find_title() {
+        let comics = comics();
+        assert_eq!(find_title(&comics, "Standards").map(|comic| comic.num), Some(927));
+        assert_eq!(find_title(&comics, "  password STRENGTH ").map(|comic| comic.num),
+                   Some(936));
+        assert!(find_title(&comics, "Random").is_none());
+        assert!(find_title(&comics, "").is_none());
+    }
+
+    #[test]
+    fn test_fetch_comic() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/comic.json"));
+
+        let comic = fetch_from(&Client::new(), server.url(), Some(927)).unwrap();
+        assert_eq!(comic.num, 927);
+        assert_eq!(comic.safe_title, "Standards");
+        assert_eq!(comic.date, "2011-07-20");
+        assert_eq!(comic.img, "https://imgs.xkcd.com/comics/standards.png");
+        assert_eq!(server.requests(), vec!["/927/info.0.json".to_owned()]);
+    }
+
+    #[test]
+    fn test_fetch_latest_comic() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/comic.json"));
+
+        fetch_from(&Client::new(), server.url(), None).unwrap();
+        assert_eq!(server.requests(), vec!["/info.0.json".to_owned()]);
+    }
+
+    #[test]
+    fn test_fetch_comic_errors() {
+        let server = FixtureServer::new("404 Not Found", "Not Found");
+        let err = fetch_from(&Client::new(), server.url(), Some(404)).unwrap_err();
+        assert_eq!(err.to_string(), "Unsuccessful response status: 404 Not Found");
+
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/malformed.json"));
+        assert!(fetch_from(&Client::new(), server.url(), Some(927)).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+#[derive(Debug, Deserialize)]
+struct CseResponse {
+    // Responses with no results omit the list of items entirely.
+    #[serde(default)]
+    pub items: Vec<CseItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CseItem {
+    pub link: String,
+}
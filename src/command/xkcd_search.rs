@@ -0,0 +1,408 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides the search engines which `xkcd search` can use to find comics.
+//!
+//! The engine is selected with the `xkcd.search_provider` config option, and
+//! may be the local comic index (`"index"`), Google Custom Search (`"cse"`),
+//! or any search API which responds with JSON (`"json"`).
+
+extern crate regex;
+
+use ::CONFIG;
+use command::quota::{self, Caller, Service};
+use command::xkcd_index::{self, INDEX_NOT_READY};
+use error::{Error, Result};
+use hyper::Url;
+use hyper::client::Client;
+use self::regex::Regex;
+use serde::Deserialize;
+use serde_json::{self, Value};
+use std::env;
+use std::io::Read;
+use std::result::Result as StdResult;
+
+lazy_static! {
+    static ref XKCD_URL_REGEX: Regex = Regex::new(r"^https?://(?:www\.)?xkcd.com/(\d+)").unwrap();
+}
+
+/// The environment variable containing the Google Custom Search API key.
+pub const CSE_API_KEY_VAR: &'static str = "GOOGLE_XKCD_CUSTOM_SEARCH_API_KEY";
+/// The environment variable containing the Google Custom Search engine ID.
+pub const CSE_ENGINE_ID_VAR: &'static str = "GOOGLE_XKCD_CUSTOM_SEARCH_ENGINE_ID";
+
+#[cfg(feature = "nightly")]
+include!("xkcd_search.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/xkcd_search.rs"));
+
+/// A search engine which can find the comic best matching a query.
+pub trait SearchProvider: Send + Sync {
+    /// Returns the number of the comic which best matches a query, or a
+    /// description of why none could be found.
+    fn search(&self, client: &Client, caller: &Caller, query: &str) -> StdResult<u32, String>;
+
+    /// Returns the service whose quota searches count against, if any.
+    fn quota_service(&self) -> Option<Service> {
+        None
+    }
+}
+
+/// Searches the local comic index.
+pub struct IndexProvider {
+    // Used until the index has been built, if configured.
+    fallback: Option<CseProvider>,
+}
+
+impl SearchProvider for IndexProvider {
+    fn search(&self, client: &Client, caller: &Caller, query: &str) -> StdResult<u32, String> {
+        if xkcd_index::len() > 0 {
+            return xkcd_index::search(query, 1)
+                .into_iter()
+                .next()
+                .map(|comic| comic.num)
+                .ok_or_else(|| format!("No comics in the index match \"{}\".", query));
+        }
+
+        match self.fallback {
+            Some(ref fallback) => fallback.search(client, caller, query),
+            None => Err(INDEX_NOT_READY.to_owned()),
+        }
+    }
+
+    fn quota_service(&self) -> Option<Service> {
+        self.fallback.as_ref().and_then(|fallback| fallback.quota_service())
+    }
+}
+
+/// Searches with Google Custom Search.
+pub struct CseProvider {
+    url: Url,
+    api_key: String,
+    engine_id: String,
+}
+
+impl SearchProvider for CseProvider {
+    fn search(&self, client: &Client, caller: &Caller, query: &str) -> StdResult<u32, String> {
+        quota::consume(Service::GoogleCse, caller)?;
+
+        let response = query_cse(client, &self.url, query, &self.api_key, &self.engine_id)
+            .map_err(|err| describe_error("Google Custom Search", err))?;
+        first_comic_id(&response)
+            .and_then(|id| id.parse::<u32>().ok())
+            .ok_or_else(|| format!("Google Custom Search found no comics matching \"{}\".", query))
+    }
+
+    fn quota_service(&self) -> Option<Service> {
+        Some(Service::GoogleCse)
+    }
+}
+
+/// Searches with an API which responds with JSON, containing the number of or
+/// a link to the best matching comic.
+pub struct JsonProvider {
+    url: Url,
+    query_param: String,
+    // The object keys and array indices leading to the result.
+    result_path: Vec<String>,
+}
+
+impl JsonProvider {
+    /// Returns a provider which sends the query in the parameter `query_param`
+    /// of `url`, and finds the result at `result_path`, a dot-separated list of
+    /// object keys and array indices (e.g. `results.0.num`).
+    pub fn new(url: Url, query_param: &str, result_path: &str) -> Self {
+        JsonProvider {
+            url: url,
+            query_param: query_param.to_owned(),
+            result_path: result_path.split('.').map(str::to_owned).collect(),
+        }
+    }
+}
+
+impl SearchProvider for JsonProvider {
+    fn search(&self, client: &Client, _caller: &Caller, query: &str) -> StdResult<u32, String> {
+        let mut url = self.url.clone();
+        url.query_pairs_mut().append_pair(&self.query_param, query);
+        let response = get_json::<Value>(client, url)
+            .map_err(|err| describe_error("The search API", err))?;
+
+        let mut value = &response;
+        for key in &self.result_path {
+            let next = match *value {
+                // An index past the end of a list of results means that there
+                // are too few results.
+                Value::Array(ref values) => {
+                    match key.parse::<usize>() {
+                        Ok(i) => values.get(i),
+                        Err(_) => None,
+                    }
+                },
+                Value::Object(ref values) => values.get(key),
+                _ => None,
+            };
+            value = match (next, value) {
+                (Some(next), _) => next,
+                (None, &Value::Array(_)) => {
+                    return Err(format!("The search API found no comics matching \"{}\".", query))
+                },
+                (None, _) => {
+                    return Err(format!("The search API's response has no value at \"{}\".",
+                                       self.result_path.join(".")))
+                },
+            };
+        }
+
+        match *value {
+            Value::Null => Err(format!("The search API found no comics matching \"{}\".", query)),
+            Value::U64(num) if num > 0 && num <= u32::max_value() as u64 => Ok(num as u32),
+            Value::I64(num) if num > 0 && num <= u32::max_value() as i64 => Ok(num as u32),
+            Value::String(ref result) => {
+                result.parse::<u32>()
+                    .ok()
+                    .or_else(|| {
+                        XKCD_URL_REGEX.captures(result)
+                            .and_then(|captures| captures.at(1))
+                            .and_then(|num| num.parse::<u32>().ok())
+                    })
+                    .ok_or_else(|| {
+                        format!("The search API returned \"{}\", which is not a comic number or \
+                                 link.",
+                                result)
+                    })
+            },
+            ref result => {
+                Err(format!("The search API returned {}, which is not a comic number or link.",
+                            result))
+            },
+        }
+    }
+}
+
+/// Returns the search provider selected in the config, or a description of
+/// why it is unavailable.
+pub fn load_provider() -> StdResult<Box<SearchProvider>, String> {
+    match CONFIG.xkcd.search_provider.as_ref() {
+        "index" => {
+            let fallback = load_cse().ok();
+            if fallback.is_none() {
+                info!("{} or {} is not set, so xkcd search only uses the local comic index",
+                      CSE_API_KEY_VAR,
+                      CSE_ENGINE_ID_VAR);
+            }
+            Ok(Box::new(IndexProvider { fallback: fallback }))
+        },
+        "cse" => Ok(Box::new(load_cse()?)),
+        "json" => {
+            let config = &CONFIG.xkcd.json_search;
+            if config.result_path.is_empty() {
+                return Err("No result path has been configured for the JSON search API."
+                    .to_owned());
+            }
+            let url = config.url
+                .parse::<Url>()
+                .map_err(|err| format!("Invalid JSON search API URL \"{}\": {}", config.url, err))?;
+            Ok(Box::new(JsonProvider::new(url, &config.query_param, &config.result_path)))
+        },
+        provider => {
+            Err(format!("Unknown search provider \"{}\". Expected \"index\", \"cse\" or \
+                         \"json\".",
+                        provider))
+        },
+    }
+}
+
+// Loads the Google Custom Search settings, returning an explanation if any of
+// them are missing or invalid.
+fn load_cse() -> StdResult<CseProvider, String> {
+    let url = CONFIG.xkcd
+        .cse_url
+        .parse::<Url>()
+        .map_err(|err| format!("Invalid custom search URL \"{}\": {}", CONFIG.xkcd.cse_url, err))?;
+
+    match (env::var(CSE_API_KEY_VAR), env::var(CSE_ENGINE_ID_VAR)) {
+        (Ok(api_key), Ok(engine_id)) => {
+            Ok(CseProvider {
+                url: url,
+                api_key: api_key,
+                engine_id: engine_id,
+            })
+        },
+        _ => {
+            Err(format!("Google Custom Search has not been configured. Ask the bot owner to set \
+                         the {} and {} environment variables.",
+                        CSE_API_KEY_VAR,
+                        CSE_ENGINE_ID_VAR))
+        },
+    }
+}
+
+// Describes a failed request to a search engine.
+fn describe_error(name: &str, err: Error) -> String {
+    match err {
+        Error::Status(status) => format!("{} responded with an error: {}.", name, status),
+        Error::Serde(err) => {
+            format!("{} returned a response which could not be understood: {}.", name, err)
+        },
+        err => format!("Failed to reach {}: {}.", name, err),
+    }
+}
+
+// Returns the number of the first comic in the search results, as a string.
+fn first_comic_id(response: &CseResponse) -> Option<&str> {
+    response.items
+        .iter()
+        .filter_map(|item| XKCD_URL_REGEX.captures_iter(&item.link).next())
+        .filter_map(|capture| capture.at(1))
+        .next()
+}
+
+fn query_cse(
+    client: &Client,
+    url: &Url,
+    query: &str,
+    search_api_key: &str,
+    search_engine_id: &str
+) -> Result<CseResponse> {
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("key", search_api_key)
+        .append_pair("cx", search_engine_id)
+        .append_pair("q", query);
+
+    get_json(client, url)
+}
+
+/// Sends a GET request, and deserializes the JSON response.
+pub fn get_json<T: Deserialize>(client: &Client, url: Url) -> Result<T> {
+    let mut response = try!(client.get(url).send().map_err(Error::from));
+    if !response.status.is_success() {
+        return Err(Error::Status(response.status));
+    }
+    let mut result = String::new();
+    try!(response.read_to_string(&mut result).map_err(Error::from));
+
+    serde_json::from_str(&result).map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use fixture::FixtureServer;
+    use hyper::Url;
+    use hyper::client::Client;
+    use command::quota::Caller;
+    use serenity::model::UserId;
+    use super::{JsonProvider, SearchProvider, describe_error, first_comic_id, query_cse};
+
+    fn search(server: &FixtureServer) -> ::error::Result<super::CseResponse> {
+        let url = server.url().parse::<Url>().unwrap();
+        query_cse(&Client::new(), &url, "standards", "test-key", "test-engine")
+    }
+
+    #[test]
+    fn test_query_cse() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/search.json"));
+
+        let response = search(&server).unwrap();
+        assert_eq!(response.items.len(), 2);
+        assert_eq!(first_comic_id(&response), Some("927"));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("key=test-key"));
+        assert!(requests[0].contains("cx=test-engine"));
+        assert!(requests[0].contains("q=standards"));
+    }
+
+    #[test]
+    fn test_query_cse_no_results() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/empty.json"));
+
+        let response = search(&server).unwrap();
+        assert!(response.items.is_empty());
+        assert_eq!(first_comic_id(&response), None);
+    }
+
+    #[test]
+    fn test_query_cse_error() {
+        let server = FixtureServer::new("403 Forbidden",
+                                        include_str!("../../tests/fixtures/xkcd/error.json"));
+
+        assert!(search(&server).is_err());
+    }
+
+    #[test]
+    fn test_query_cse_malformed_json() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/malformed.json"));
+
+        assert!(search(&server).is_err());
+    }
+
+    #[test]
+    fn test_describe_cse_error() {
+        let server = FixtureServer::new("403 Forbidden",
+                                        include_str!("../../tests/fixtures/xkcd/error.json"));
+
+        let err = describe_error("Google Custom Search", search(&server).unwrap_err());
+        assert_eq!(err, "Google Custom Search responded with an error: 403 Forbidden.");
+    }
+
+    fn json_search(server: &FixtureServer, result_path: &str) -> Result<u32, String> {
+        let url = format!("{}search", server.url()).parse::<Url>().unwrap();
+        let caller = Caller {
+            user: UserId(1),
+            guild: None,
+        };
+        JsonProvider::new(url, "query", result_path).search(&Client::new(), &caller, "standards")
+    }
+
+    #[test]
+    fn test_json_search() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/api.json"));
+
+        assert_eq!(json_search(&server, "results.0.num"), Ok(927));
+        assert_eq!(json_search(&server, "results.1.link"), Ok(327));
+        assert_eq!(server.requests()[0], "/search?query=standards");
+    }
+
+    #[test]
+    fn test_json_search_no_results() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/api.json"));
+
+        assert_eq!(json_search(&server, "results.2.num"),
+                   Err("The search API found no comics matching \"standards\".".to_owned()));
+    }
+
+    #[test]
+    fn test_json_search_missing_field() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/api.json"));
+
+        assert_eq!(json_search(&server, "items.0.num"),
+                   Err("The search API's response has no value at \"items.0.num\".".to_owned()));
+    }
+
+    #[test]
+    fn test_json_search_invalid_result() {
+        let server = FixtureServer::new("200 OK",
+                                        include_str!("../../tests/fixtures/xkcd/api.json"));
+
+        assert_eq!(json_search(&server, "results.0.title"),
+                   Err("The search API returned \"Standards\", which is not a comic number or \
+                        link."
+                       .to_owned()));
+    }
+}
@@ -8,4 +8,166 @@ pub struct Config {
     pub source_url: String,
     /// The authors to use in author checks for permissions.
     pub owners: HashSet<u64>,
+    /// The default limits on tag creation. Guild administrators may override
+    /// these for their own guild.
+    #[serde(default)]
+    pub tag_limits: TagLimits,
+    /// The minimum number of seconds between two autoresponder replies in the
+    /// same channel.
+    #[serde(default = "default_autoresponder_cooldown")]
+    pub autoresponder_cooldown: u64,
+    /// Settings for the Markov chain commands.
+    #[serde(default)]
+    pub markov: MarkovConfig,
+    /// Settings for the Wolfram|Alpha command.
+    #[serde(default)]
+    pub wolfram: WolframConfig,
+    /// Settings for the xkcd command.
+    #[serde(default)]
+    pub xkcd: XkcdConfig,
+    /// The daily limits on calls to paid external APIs.
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    /// Settings for slow commands, which run in the background.
+    #[serde(default)]
+    pub jobs: JobConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TagLimits {
+    /// The maximum number of tags a single user may own in a guild.
+    #[serde(default = "default_tags_per_user")]
+    pub per_user: usize,
+    /// The maximum number of tags a guild may contain.
+    #[serde(default = "default_tags_per_guild")]
+    pub per_guild: usize,
+    /// The maximum length of a tag's content, in characters.
+    #[serde(default = "default_tag_content_length")]
+    pub content_length: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MarkovConfig {
+    /// The directory in which trained Markov chains are cached. If not set,
+    /// chains are retrained from their corpus every time the bot starts.
+    pub cache_dir: Option<String>,
+    /// The name of the corpus used by the `fuyu` command.
+    #[serde(default = "default_markov_corpus")]
+    pub default_corpus: String,
+    /// The corpora available to the `markov` command, by name.
+    #[serde(default = "default_markov_corpora")]
+    pub corpora: HashMap<String, CorpusConfig>,
+    /// Words which generated text may not contain, matched case-insensitively
+    /// as whole words.
+    /// Text containing mentions, `@everyone`, or `@here` is always rejected.
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+    /// The directory in which the corpora of users who have consented to
+    /// being impersonated are stored.
+    #[serde(default = "default_user_corpus_dir")]
+    pub user_corpus_dir: String,
+    /// The minimum number of seconds between two chat mode replies in the
+    /// same channel.
+    #[serde(default = "default_chat_cooldown")]
+    pub chat_cooldown: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CorpusConfig {
+    /// The path to the double newline delimited corpus file.
+    pub path: String,
+    /// The guild which owns the corpus. Corpora without an owner are available
+    /// in every guild.
+    #[serde(default)]
+    pub guild: Option<u64>,
+    /// The order of the Markov chain, i.e. the number of preceding words used
+    /// to choose the next one. Higher orders produce more coherent text, but
+    /// are more likely to reproduce the corpus verbatim.
+    #[serde(default = "default_markov_order")]
+    pub order: usize,
+    /// Whether to reject generated text which is identical to a line of the
+    /// corpus.
+    #[serde(default = "default_reject_verbatim")]
+    pub reject_verbatim: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WolframConfig {
+    /// The number of seconds for which query results are cached.
+    #[serde(default = "default_wolfram_cache_ttl")]
+    pub cache_ttl: i64,
+    /// The base URL of the Wolfram|Alpha API, to which the name of the method
+    /// is appended.
+    #[serde(default = "default_wolfram_api_url")]
+    pub api_url: String,
+    /// The URL of the Wolfram|Alpha Short Answers API.
+    #[serde(default = "default_wolfram_short_answer_url")]
+    pub short_answer_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct XkcdConfig {
+    /// The base URL of the xkcd API, from which comics are retrieved.
+    #[serde(default = "default_xkcd_api_url")]
+    pub api_url: String,
+    /// The URL of the Google Custom Search API used to search for comics.
+    #[serde(default = "default_cse_url")]
+    pub cse_url: String,
+    /// The number of seconds between checks for new comics to add to the
+    /// local comic index.
+    #[serde(default = "default_xkcd_sync_interval")]
+    pub sync_interval: u64,
+    /// The number of seconds between checks for new comics to post to
+    /// subscribed channels.
+    #[serde(default = "default_xkcd_poll_interval")]
+    pub poll_interval: u64,
+    /// The provider used to search for comics: `"index"` (the local comic
+    /// index), `"cse"` (Google Custom Search) or `"json"` (a JSON search API).
+    #[serde(default = "default_xkcd_search_provider")]
+    pub search_provider: String,
+    /// Settings for the `"json"` search provider.
+    #[serde(default)]
+    pub json_search: JsonSearchConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JsonSearchConfig {
+    /// The URL of the search API.
+    #[serde(default)]
+    pub url: String,
+    /// The name of the URL parameter in which the query is sent.
+    #[serde(default = "default_json_search_query_param")]
+    pub query_param: String,
+    /// The location of the number of or link to the best matching comic in a
+    /// response, as dot-separated object keys and array indices, e.g.
+    /// `results.0.num`.
+    #[serde(default)]
+    pub result_path: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuotaConfig {
+    /// The limits on Wolfram|Alpha API calls.
+    #[serde(default = "default_wolfram_quota")]
+    pub wolfram: QuotaLimits,
+    /// The limits on Google Custom Search API calls.
+    #[serde(default = "default_google_cse_quota")]
+    pub google_cse: QuotaLimits,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QuotaLimits {
+    /// The maximum number of calls a single user may make per day, or `None`
+    /// for no limit.
+    pub per_user: Option<u64>,
+    /// The maximum number of calls which may be made from a single guild per
+    /// day, or `None` for no limit.
+    pub per_guild: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobConfig {
+    /// The number of seconds to wait for a slow command before giving up.
+    #[serde(default = "default_job_timeout")]
+    pub timeout: u64,
 }
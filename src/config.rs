@@ -9,7 +9,7 @@
 
 use error::Result;
 use serde_json;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{ErrorKind, Read};
 
@@ -58,6 +58,188 @@ impl Default for Config {
             command_prefix: ";".to_owned(),
             owners: HashSet::new(),
             source_url: "https://github.com/indiv0/smexybot".to_owned(),
+            tag_limits: Default::default(),
+            autoresponder_cooldown: default_autoresponder_cooldown(),
+            markov: Default::default(),
+            wolfram: Default::default(),
+            xkcd: Default::default(),
+            quota: Default::default(),
+            jobs: Default::default(),
         }
     }
 }
+
+fn default_autoresponder_cooldown() -> u64 {
+    30
+}
+
+impl Default for TagLimits {
+    fn default() -> TagLimits {
+        TagLimits {
+            per_user: default_tags_per_user(),
+            per_guild: default_tags_per_guild(),
+            content_length: default_tag_content_length(),
+        }
+    }
+}
+
+fn default_tags_per_user() -> usize {
+    50
+}
+
+fn default_tags_per_guild() -> usize {
+    1000
+}
+
+fn default_tag_content_length() -> usize {
+    2000
+}
+
+impl Default for MarkovConfig {
+    fn default() -> MarkovConfig {
+        MarkovConfig {
+            cache_dir: None,
+            default_corpus: default_markov_corpus(),
+            corpora: default_markov_corpora(),
+            blocklist: Vec::new(),
+            user_corpus_dir: default_user_corpus_dir(),
+            chat_cooldown: default_chat_cooldown(),
+        }
+    }
+}
+
+fn default_markov_corpus() -> String {
+    "fuyu".to_owned()
+}
+
+fn default_markov_corpora() -> HashMap<String, CorpusConfig> {
+    let mut corpora = HashMap::new();
+    corpora.insert(default_markov_corpus(),
+                   CorpusConfig {
+                       path: "logs/fuyu.txt".to_owned(),
+                       guild: None,
+                       order: default_markov_order(),
+                       reject_verbatim: default_reject_verbatim(),
+                   });
+    corpora
+}
+
+fn default_user_corpus_dir() -> String {
+    "logs/users".to_owned()
+}
+
+fn default_chat_cooldown() -> u64 {
+    10
+}
+
+fn default_markov_order() -> usize {
+    1
+}
+
+fn default_reject_verbatim() -> bool {
+    true
+}
+
+impl Default for WolframConfig {
+    fn default() -> WolframConfig {
+        WolframConfig {
+            cache_ttl: default_wolfram_cache_ttl(),
+            api_url: default_wolfram_api_url(),
+            short_answer_url: default_wolfram_short_answer_url(),
+        }
+    }
+}
+
+fn default_wolfram_cache_ttl() -> i64 {
+    60 * 60 * 24
+}
+
+fn default_wolfram_api_url() -> String {
+    "https://api.wolframalpha.com/v2/".to_owned()
+}
+
+fn default_wolfram_short_answer_url() -> String {
+    "https://api.wolframalpha.com/v1/result".to_owned()
+}
+
+impl Default for XkcdConfig {
+    fn default() -> XkcdConfig {
+        XkcdConfig {
+            api_url: default_xkcd_api_url(),
+            cse_url: default_cse_url(),
+            sync_interval: default_xkcd_sync_interval(),
+            poll_interval: default_xkcd_poll_interval(),
+            search_provider: default_xkcd_search_provider(),
+            json_search: Default::default(),
+        }
+    }
+}
+
+fn default_xkcd_api_url() -> String {
+    "https://xkcd.com/".to_owned()
+}
+
+fn default_cse_url() -> String {
+    "https://www.googleapis.com/customsearch/v1".to_owned()
+}
+
+fn default_xkcd_sync_interval() -> u64 {
+    60 * 60
+}
+
+fn default_xkcd_poll_interval() -> u64 {
+    15 * 60
+}
+
+fn default_xkcd_search_provider() -> String {
+    "index".to_owned()
+}
+
+impl Default for JsonSearchConfig {
+    fn default() -> JsonSearchConfig {
+        JsonSearchConfig {
+            url: String::new(),
+            query_param: default_json_search_query_param(),
+            result_path: String::new(),
+        }
+    }
+}
+
+fn default_json_search_query_param() -> String {
+    "q".to_owned()
+}
+
+impl Default for QuotaConfig {
+    fn default() -> QuotaConfig {
+        QuotaConfig {
+            wolfram: default_wolfram_quota(),
+            google_cse: default_google_cse_quota(),
+        }
+    }
+}
+
+// The free Wolfram|Alpha plan allows 2000 calls per month.
+fn default_wolfram_quota() -> QuotaLimits {
+    QuotaLimits {
+        per_user: Some(20),
+        per_guild: Some(60),
+    }
+}
+
+// The free Google Custom Search plan allows 100 calls per day.
+fn default_google_cse_quota() -> QuotaLimits {
+    QuotaLimits {
+        per_user: Some(10),
+        per_guild: Some(50),
+    }
+}
+
+impl Default for JobConfig {
+    fn default() -> JobConfig {
+        JobConfig { timeout: default_job_timeout() }
+    }
+}
+
+fn default_job_timeout() -> u64 {
+    30
+}
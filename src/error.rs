@@ -8,6 +8,7 @@
 // except according to those terms.
 
 use hyper;
+use hyper::status::StatusCode;
 use serde_json;
 use std::error::Error as StdError;
 use std::fmt;
@@ -27,6 +28,8 @@ pub enum Error {
     Io(io::Error),
     /// A `serde` crate error.
     Serde(serde_json::Error),
+    /// An HTTP request was answered with an unsuccessful status.
+    Status(StatusCode),
     /// Error while parsing a URL.
     UrlParse(url::ParseError),
 }
@@ -39,6 +42,7 @@ impl fmt::Display for Error {
             Hyper(ref e) => e.fmt(f),
             Io(ref e) => e.fmt(f),
             Serde(ref e) => e.fmt(f),
+            Status(ref status) => write!(f, "Unsuccessful response status: {}", status),
             UrlParse(ref e) => e.fmt(f),
         }
     }
@@ -52,6 +56,7 @@ impl StdError for Error {
             Hyper(ref e) => e.description(),
             Io(ref e) => e.description(),
             Serde(ref e) => e.description(),
+            Status(_) => "Unsuccessful response status",
             UrlParse(ref e) => e.description(),
         }
     }
@@ -63,6 +68,7 @@ impl StdError for Error {
             Hyper(ref e) => e.cause(),
             Io(ref e) => e.cause(),
             Serde(ref e) => e.cause(),
+            Status(_) => None,
             UrlParse(ref e) => e.cause(),
         }
     }
@@ -0,0 +1,86 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides a local stand-in HTTP server which serves recorded responses, so
+//! that the HTTP integrations can be tested without network access.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// An HTTP server which answers every request with the same recorded
+/// response.
+#[derive(Debug)]
+pub struct FixtureServer {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FixtureServer {
+    /// Starts a server on an unused local port which responds with the
+    /// specified status line (e.g. `"200 OK"`) and body.
+    pub fn new(status: &'static str, body: &'static str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind fixture server");
+        let url = format!("http://{}/",
+                          listener.local_addr().expect("Failed to get fixture server address"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let log = requests.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => break,
+            };
+
+            let mut reader = BufReader::new(stream.try_clone()
+                .expect("Failed to clone fixture stream"));
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            // Skip the headers. Requests are not expected to have a body.
+            loop {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if line.trim().is_empty() => break,
+                    Ok(_) => {},
+                }
+            }
+
+            // Record the target of the request, e.g. `/query?input=pi`.
+            if let Some(target) = request_line.split_whitespace().nth(1) {
+                log.lock().expect("Failed to lock fixture requests").push(target.to_owned());
+            }
+
+            let _ = write!(stream,
+                           "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                            Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status,
+                           body.len(),
+                           body);
+        });
+
+        FixtureServer {
+            url: url,
+            requests: requests,
+        }
+    }
+
+    /// Returns the base URL of the server, including a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the targets of the requests received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().expect("Failed to lock fixture requests").clone()
+    }
+}
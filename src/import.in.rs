@@ -0,0 +1,27 @@
+#[derive(Debug, Deserialize)]
+struct JsonExport {
+    channel: JsonChannel,
+    messages: Vec<JsonMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonChannel {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonMessage {
+    timestamp: String,
+    #[serde(default)]
+    content: String,
+    author: JsonAuthor,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonAuthor {
+    id: String,
+    name: String,
+    #[serde(default, rename = "isBot")]
+    is_bot: bool,
+}
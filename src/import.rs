@@ -0,0 +1,451 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides the `import` subcommand, which converts Discord chat exports into
+//! the double newline delimited corpus format used by the Markov chain
+//! commands.
+//!
+//! Both the JSON and CSV formats produced by
+//! [DiscordChatExporter][exporter] are supported. CSV exports do not include
+//! any channel information, so the name of the file (without its extension) is
+//! used as the channel name when filtering by channel.
+//!
+//! [exporter]: https://github.com/Tyrrrz/DiscordChatExporter
+
+extern crate regex;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, UTC};
+use self::regex::Regex;
+use serde_json;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Describes the usage of the `import` subcommand.
+pub const USAGE: &'static str = "Usage: smexybot import [OPTIONS] <EXPORT>...
+
+Converts Discord chat exports (JSON or CSV) into a Markov chain corpus.
+
+Options:
+    --author <ID|NAME>     Only import messages by this author (repeatable)
+    --channel <ID|NAME>    Only import messages from this channel (repeatable)
+    --after <DATE>         Only import messages sent after this date
+    --before <DATE>        Only import messages sent before this date
+    --output <FILE>        The corpus file to write (default: logs/fuyu.txt)
+    --append               Append to the corpus instead of replacing it
+
+Dates may be given as YYYY-MM-DD or in RFC 3339 format.";
+
+lazy_static! {
+    static ref CODE_BLOCK_REGEX: Regex = Regex::new(r"(?s)```.*?```|`[^`]*`").unwrap();
+    static ref MENTION_REGEX: Regex = Regex::new(r"<(@[!&]?|#)\d+>|@everyone|@here").unwrap();
+    static ref URL_REGEX: Regex = Regex::new(r"https?://\S+").unwrap();
+}
+
+#[cfg(feature = "nightly")]
+include!("import.in.rs");
+
+#[cfg(feature = "with-syntex")]
+include!(concat!(env!("OUT_DIR"), "/import.rs"));
+
+// A message read from an export, in a format-independent representation.
+#[derive(Debug)]
+struct ExportedMessage {
+    author_id: String,
+    author_name: String,
+    channel_id: String,
+    channel_name: String,
+    timestamp: Option<DateTime<UTC>>,
+    content: String,
+    is_bot: bool,
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    authors: Vec<String>,
+    channels: Vec<String>,
+    after: Option<DateTime<UTC>>,
+    before: Option<DateTime<UTC>>,
+    output: Option<String>,
+    append: bool,
+    files: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--author" => options.authors.push(next_value(&arg, args.next())?),
+                "--channel" => options.channels.push(next_value(&arg, args.next())?),
+                "--after" => options.after = Some(parse_date(&next_value(&arg, args.next())?)?),
+                "--before" => options.before = Some(parse_date(&next_value(&arg, args.next())?)?),
+                "--output" => options.output = Some(next_value(&arg, args.next())?),
+                "--append" => options.append = true,
+                option if option.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", option));
+                },
+                _ => options.files.push(arg.clone()),
+            }
+        }
+
+        if options.files.is_empty() {
+            return Err("No export files specified".to_owned());
+        }
+
+        Ok(options)
+    }
+
+    // Checks whether a message passes the author, channel and date filters.
+    fn matches(&self, message: &ExportedMessage) -> bool {
+        if message.is_bot {
+            return false;
+        }
+
+        if !self.authors.is_empty() &&
+           !self.authors.iter().any(|a| *a == message.author_id || *a == message.author_name) {
+            return false;
+        }
+
+        if !self.channels.is_empty() &&
+           !self.channels.iter().any(|c| *c == message.channel_id || *c == message.channel_name) {
+            return false;
+        }
+
+        if self.after.is_some() || self.before.is_some() {
+            let timestamp = match message.timestamp {
+                Some(timestamp) => timestamp,
+                // Messages with unknown dates cannot satisfy a date filter.
+                None => return false,
+            };
+            if self.after.map_or(false, |after| timestamp < after) ||
+               self.before.map_or(false, |before| timestamp >= before) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Runs the `import` subcommand with the specified arguments.
+pub fn run(args: Vec<String>) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    let output = options.output.clone().unwrap_or_else(|| "logs/fuyu.txt".to_owned());
+
+    // When appending, lines already present in the corpus are not imported
+    // again.
+    let mut seen = HashSet::new();
+    if options.append {
+        let existing = match read_file(&output) {
+            Ok(existing) => existing,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Failed to read {}: {}", output, err)),
+        };
+        for line in existing.split("\n\n") {
+            seen.insert(line.trim().to_owned());
+        }
+    }
+
+    let mut lines = Vec::new();
+    for name in &options.files {
+        let messages = read_export(name)?;
+        let total = messages.len();
+        let mut imported = 0;
+        for message in messages.into_iter().filter(|m| options.matches(m)) {
+            let line = clean(&message.content);
+            if line.is_empty() || !seen.insert(line.clone()) {
+                continue;
+            }
+            lines.push(line);
+            imported += 1;
+        }
+        info!("Imported {} of {} messages from {}", imported, total, name);
+    }
+
+    if let Some(dir) = Path::new(&output).parent() {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("Failed to create directory for {}: {}", output, err))?;
+    }
+    let mut file = OpenOptions::new().write(true)
+        .create(true)
+        .append(options.append)
+        .truncate(!options.append)
+        .open(&output)
+        .map_err(|err| format!("Failed to open {}: {}", output, err))?;
+    let mut contents = lines.join("\n\n");
+    if options.append && !lines.is_empty() {
+        contents = format!("\n\n{}", contents);
+    }
+    file.write_all(contents.as_bytes())
+        .map_err(|err| format!("Failed to write to {}: {}", output, err))?;
+
+    println!("Wrote {} lines to {}", lines.len(), output);
+
+    Ok(())
+}
+
+fn next_value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for option: {}", option))
+}
+
+// Parses a date given either as `YYYY-MM-DD` or in RFC 3339 format.
+fn parse_date(date: &str) -> Result<DateTime<UTC>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&UTC));
+    }
+
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| DateTime::from_utc(date.and_hms(0, 0, 0), UTC))
+        .map_err(|_| format!("Invalid date: {}", date))
+}
+
+// Parses the timestamp of an exported message, which depending on the version
+// of the exporter is either in RFC 3339 format or in a localized format.
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<UTC>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp) {
+        return Some(timestamp.with_timezone(&UTC));
+    }
+
+    NaiveDateTime::parse_from_str(timestamp, "%d-%b-%y %I:%M %p")
+        .ok()
+        .map(|timestamp| DateTime::from_utc(timestamp, UTC))
+}
+
+// Removes code blocks, URLs and mentions from a message, and collapses its
+// whitespace.
+fn clean(content: &str) -> String {
+    let content = CODE_BLOCK_REGEX.replace_all(content, "");
+    let content = URL_REGEX.replace_all(&content, "");
+    let content = MENTION_REGEX.replace_all(&content, "");
+    content.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn read_file(name: &str) -> ::std::io::Result<String> {
+    let mut file = File::open(name)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+fn read_export(name: &str) -> Result<Vec<ExportedMessage>, String> {
+    let contents = read_file(name).map_err(|err| format!("Failed to read {}: {}", name, err))?;
+    let path = Path::new(name);
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => read_json_export(&contents),
+        Some("csv") => {
+            let channel = path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_owned();
+            read_csv_export(&contents, channel)
+        },
+        _ => Err(format!("Unsupported export format: {}", name)),
+    }
+    .map_err(|err| format!("Failed to parse {}: {}", name, err))
+}
+
+fn read_json_export(contents: &str) -> Result<Vec<ExportedMessage>, String> {
+    let export: JsonExport = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+    let channel = export.channel;
+
+    Ok(export.messages
+        .into_iter()
+        .map(|message| {
+            ExportedMessage {
+                author_id: message.author.id,
+                author_name: message.author.name,
+                channel_id: channel.id.clone(),
+                channel_name: channel.name.clone(),
+                timestamp: parse_timestamp(&message.timestamp),
+                content: message.content,
+                is_bot: message.author.is_bot,
+            }
+        })
+        .collect())
+}
+
+fn read_csv_export(contents: &str, channel: String) -> Result<Vec<ExportedMessage>, String> {
+    let mut records = parse_csv(contents).into_iter();
+    let header = records.next().ok_or_else(|| "Missing CSV header".to_owned())?;
+    let column = |name: &str| {
+        header.iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("Missing CSV column: {}", name))
+    };
+    let author_id = column("AuthorID")?;
+    let author_name = column("Author")?;
+    let date = column("Date")?;
+    let content = column("Content")?;
+
+    Ok(records.filter(|record| record.len() == header.len())
+        .map(|mut record| {
+            ExportedMessage {
+                author_id: record[author_id].clone(),
+                author_name: record[author_name].clone(),
+                channel_id: channel.clone(),
+                channel_name: channel.clone(),
+                timestamp: parse_timestamp(&record[date]),
+                content: ::std::mem::replace(&mut record[content], String::new()),
+                is_bot: false,
+            }
+        })
+        .collect())
+}
+
+// Parses CSV data into records, handling quoted fields containing commas,
+// newlines and escaped quotes.
+fn parse_csv(contents: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            },
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(::std::mem::replace(&mut field, String::new())),
+            '\r' if !quoted => {},
+            '\n' if !quoted => {
+                record.push(::std::mem::replace(&mut field, String::new()));
+                records.push(::std::mem::replace(&mut record, Vec::new()));
+            },
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, UTC};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use super::{ExportedMessage, Options, clean, parse_csv, parse_date, read_file, run};
+
+    fn message(author: &str, channel: &str, timestamp: Option<DateTime<UTC>>) -> ExportedMessage {
+        ExportedMessage {
+            author_id: format!("{}-id", author),
+            author_name: author.to_owned(),
+            channel_id: format!("{}-id", channel),
+            channel_name: channel.to_owned(),
+            timestamp: timestamp,
+            content: "hello".to_owned(),
+            is_bot: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let records = parse_csv("a,b,c\r\n1,\"two, too\",\"say \"\"hi\"\"\"\n\"multi\nline\",,x");
+
+        assert_eq!(records,
+                   vec![vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+                        vec!["1".to_owned(), "two, too".to_owned(), "say \"hi\"".to_owned()],
+                        vec!["multi\nline".to_owned(), String::new(), "x".to_owned()]]);
+    }
+
+    #[test]
+    fn test_parse_csv_trailing_newline() {
+        assert_eq!(parse_csv("a,b\n"), vec![vec!["a".to_owned(), "b".to_owned()]]);
+        assert!(parse_csv("").is_empty());
+    }
+
+    #[test]
+    fn test_clean() {
+        assert_eq!(clean("look at `this` and\n```\nfn main() {}\n``` now"),
+                   "look at and now");
+        assert_eq!(clean("see https://example.com/a?b=c <@1234> there"), "see there");
+        assert_eq!(clean("hey <@!42> <#7> <@&9> @everyone @here"), "hey");
+        assert_eq!(clean("   "), "");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2016-11-05").unwrap(), UTC.ymd(2016, 11, 5).and_hms(0, 0, 0));
+        assert_eq!(parse_date("2016-11-05T12:30:00+02:00").unwrap(),
+                   UTC.ymd(2016, 11, 5).and_hms(10, 30, 0));
+        assert_eq!(parse_date("05/11/2016").unwrap_err(), "Invalid date: 05/11/2016");
+    }
+
+    #[test]
+    fn test_matches_authors_and_channels() {
+        let options = Options {
+            authors: vec!["alice".to_owned(), "bob-id".to_owned()],
+            channels: vec!["general".to_owned()],
+            ..Default::default()
+        };
+
+        assert!(options.matches(&message("alice", "general", None)));
+        assert!(options.matches(&message("bob", "general", None)));
+        assert!(!options.matches(&message("carol", "general", None)));
+        assert!(!options.matches(&message("alice", "random", None)));
+
+        let mut bot = message("alice", "general", None);
+        bot.is_bot = true;
+        assert!(!options.matches(&bot));
+        assert!(Options::default().matches(&message("carol", "random", None)));
+    }
+
+    #[test]
+    fn test_matches_dates() {
+        let options = Options {
+            after: Some(UTC.ymd(2016, 1, 1).and_hms(0, 0, 0)),
+            before: Some(UTC.ymd(2016, 2, 1).and_hms(0, 0, 0)),
+            ..Default::default()
+        };
+        let at = |month, day| Some(UTC.ymd(2016, month, day).and_hms(0, 0, 0));
+
+        assert!(options.matches(&message("alice", "general", at(1, 1))));
+        assert!(options.matches(&message("alice", "general", at(1, 31))));
+        assert!(!options.matches(&message("alice", "general", at(2, 1))));
+        assert!(!options.matches(&message("alice", "general", None)));
+    }
+
+    #[test]
+    fn test_run_creates_output_directory() {
+        let dir = env::temp_dir().join("smexybot-import-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let export = dir.join("general.csv");
+        let output = dir.join("corpora").join("general.txt");
+        File::create(&export)
+            .unwrap()
+            .write_all(b"AuthorID,Author,Date,Content\n1,alice,05-Nov-16 01:30 PM,hello there\n")
+            .unwrap();
+
+        run(vec!["--output".to_owned(),
+                 output.to_string_lossy().into_owned(),
+                 export.to_string_lossy().into_owned()])
+            .unwrap();
+
+        assert_eq!(read_file(&output.to_string_lossy()).unwrap(), "hello there");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides an executor for slow commands, which runs their work on a
+//! separate thread with a timeout, so that the thread handling the command is
+//! free to handle others.
+//!
+//! While a job runs, a "working..." message is shown in its channel, which is
+//! then edited to show the job's result, or an explanation of why it has none.
+//! Users may cancel their own pending jobs with the `cancel` command.
+
+use ::CONFIG;
+use serenity::client::Context;
+use serenity::model::{ChannelId, Message, UserId};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use util::{check_msg, stringify};
+
+lazy_static! {
+    static ref JOBS: Mutex<HashMap<usize, PendingJob>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_JOB_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// A job which has not yet finished.
+struct PendingJob {
+    user: UserId,
+    channel: ChannelId,
+    // Stops waiting for the job.
+    cancel: Box<Fn() + Send>,
+}
+
+enum Event<T> {
+    Done(Result<T, String>),
+    // The job's thread panicked before returning its output.
+    Panicked,
+    Cancelled,
+}
+
+/// Runs `job` on a separate thread, then passes its output to `render`, along
+/// with the "working..." message shown while it ran.
+///
+/// This returns as soon as the "working..." message has been sent. The job is
+/// waited for, and rendered, by a second thread. If the job fails, times out,
+/// or is cancelled, the "working..." message is edited to say so instead. A
+/// job which times out cannot be stopped, so it continues to run in the
+/// background, but its output is discarded.
+pub fn run<T, J, R>(
+    context: &Context,
+    message: &Message,
+    description: &str,
+    job: J,
+    render: R
+) -> Result<(), String>
+    where T: Send + 'static,
+          J: FnOnce() -> Result<T, String> + Send + 'static,
+          R: FnOnce(&Context, &Message, T) -> Result<(), String> + Send + 'static,
+{
+    let working = context.say(&format!("Working on {}... (use `{}cancel` to cancel)",
+                      description,
+                      CONFIG.command_prefix))
+        .map_err(stringify)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed);
+    {
+        let cancel = sender.clone();
+        let mut jobs = JOBS.lock().expect("Failed to lock jobs");
+        jobs.insert(id,
+                    PendingJob {
+                        user: message.author.id,
+                        channel: message.channel_id,
+                        cancel: Box::new(move || {
+                            let _ = cancel.send(Event::Cancelled);
+                        }),
+                    });
+    }
+
+    spawn(job, sender);
+
+    let context = context.clone();
+    let description = description.to_owned();
+    thread::spawn(move || {
+        let timeout = CONFIG.jobs.timeout;
+        let event = receiver.recv_timeout(Duration::from_secs(timeout));
+        JOBS.lock().expect("Failed to lock jobs").remove(&id);
+
+        let result = match event {
+            Ok(Event::Done(Ok(output))) => render(&context, &working, output),
+            Ok(Event::Done(Err(err))) => Err(err),
+            Ok(Event::Cancelled) => Err(format!("Cancelled {}.", description)),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(format!("Gave up on {} after {} seconds.", description, timeout))
+            },
+            Ok(Event::Panicked) |
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(format!("Something went wrong while working on {}.", description))
+            },
+        };
+        if let Err(err) = result {
+            edit_text(&context, &working, &err);
+        }
+    });
+
+    Ok(())
+}
+
+// Runs a job on a new thread, and sends its output once it finishes. The job is
+// run on a thread of its own, so that its panics are caught and reported rather
+// than leaving the watcher waiting until it times out.
+fn spawn<T, J>(job: J, sender: Sender<Event<T>>)
+    where T: Send + 'static,
+          J: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    thread::spawn(move || {
+        let event = match thread::spawn(job).join() {
+            Ok(output) => Event::Done(output),
+            Err(_) => Event::Panicked,
+        };
+        // Sending fails if the job has already timed out or been cancelled, in
+        // which case nobody is waiting for its output.
+        let _ = sender.send(event);
+    });
+}
+
+/// Replaces the content of a "working..." message with some text.
+pub fn edit_text(context: &Context, working: &Message, text: &str) {
+    check_msg(context.edit_message(working.channel_id, working.id, text, |e| e));
+}
+
+command!(cancel(context, message, _args) {
+    let jobs = JOBS.lock().expect("Failed to lock jobs");
+    let mut cancelled = 0;
+    for job in jobs.values() {
+        if job.user == message.author.id && job.channel == message.channel_id {
+            (job.cancel)();
+            cancelled += 1;
+        }
+    }
+
+    if cancelled == 0 {
+        check_msg(context.say("You have no pending jobs in this channel."));
+    }
+});
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use super::{Event, spawn};
+
+    #[test]
+    fn test_spawn() {
+        let (sender, receiver) = mpsc::channel();
+        spawn(|| Ok(42), sender);
+
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(Event::Done(Ok(42))) => {},
+            _ => panic!("Expected the job's output"),
+        }
+    }
+
+    #[test]
+    fn test_spawn_panicking_job() {
+        let (sender, receiver) = mpsc::channel::<Event<()>>();
+        // Pending jobs keep a sender of their own for cancellation, so the
+        // channel is never disconnected while the job runs.
+        let _cancel = sender.clone();
+        spawn(|| panic!("Job failed"), sender);
+
+        match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(Event::Panicked) => {},
+            _ => panic!("Expected the job's panic to be reported"),
+        }
+    }
+}
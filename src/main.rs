@@ -37,7 +37,6 @@
 extern crate chrono;
 extern crate env_logger;
 extern crate hyper;
-#[cfg(any(feature = "roll", feature = "wolfram", feature = "xkcd"))]
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
@@ -56,17 +55,25 @@ mod command;
 mod config;
 mod counter;
 mod error;
+#[cfg(all(test, any(feature = "wolfram", feature = "xkcd")))]
+mod fixture;
+mod import;
+#[cfg(any(feature = "fuyu", feature = "wolfram", feature = "xkcd"))]
+mod job;
+mod store;
 mod util;
 
 use chrono::{DateTime, UTC};
 use config::Config;
 use counter::CommandCounter;
 use serenity::Client;
-use serenity::client::LoginType;
+use serenity::client::{Context, LoginType};
 use serenity::ext::framework::Framework;
-use serenity::model::UserId;
+use serenity::model::{Message, Reaction, UserId};
 use std::collections::HashMap;
 use std::env;
+use std::io::{self, Write};
+use std::process;
 use util::{check_msg, timestamp_to_string};
 
 const RATE_LIMIT_MESSAGE: &'static str = "Try this again in %time% seconds.";
@@ -80,9 +87,21 @@ fn main() {
     // Initialize the `env_logger` to provide logging output.
     env_logger::init().expect("Failed to initialize env_logger");
 
+    // Run the corpus import tool instead of the bot if requested.
+    let mut args = env::args().skip(1);
+    if let Some("import") = args.next().as_ref().map(String::as_ref) {
+        if let Err(err) = import::run(args.collect()) {
+            let _ = writeln!(io::stderr(), "Error: {}\n\n{}", err, import::USAGE);
+            process::exit(1);
+        }
+        return;
+    }
+
     // Initialize the `UPTIME` variable.
     debug!("Initialized at: {}", timestamp_to_string(&*UPTIME));
 
+    check_credentials();
+
     // Create a client for a user.
     let (_, mut client) = login();
 
@@ -106,6 +125,20 @@ fn main() {
         );
     });
 
+    #[cfg(feature = "fuyu")]
+    {
+        command::fuyu::preload();
+    }
+    #[cfg(feature = "xkcd")]
+    {
+        command::xkcd_index::start_sync();
+        command::xkcd::start_poller();
+    }
+
+    client.on_message(handle_message);
+    client.on_reaction_add(handle_reaction_add);
+    client.on_reaction_remove(handle_reaction_remove);
+
     client.with_framework(build_framework);
 
     if let Err(err) = client.start_autosharded() {
@@ -113,6 +146,24 @@ fn main() {
     }
 }
 
+// Warns about any commands which are disabled because their API credentials
+// have not been configured.
+fn check_credentials() {
+    #[cfg(feature = "wolfram")]
+    {
+        if !command::wolfram_alpha::is_enabled() {
+            warn!("{} is not set, so the wolfram command is disabled",
+                  command::wolfram_alpha::APP_ID_VAR);
+        }
+    }
+    #[cfg(feature = "xkcd")]
+    {
+        if let Some(err) = command::xkcd::search_unavailable() {
+            warn!("xkcd search is disabled: {}", err);
+        }
+    }
+}
+
 // Configures the `Framework` used by serenity, and registers the handlers for
 // any enabled commands.
 fn build_framework(framework: Framework) -> Framework {
@@ -146,9 +197,18 @@ fn build_framework(framework: Framework) -> Framework {
             }
         });
 
+    #[cfg(any(feature = "fuyu", feature = "wolfram", feature = "xkcd"))]
+    {
+        framework = framework.command("cancel", |c| c.exec(job::cancel));
+    }
+    #[cfg(feature = "calc")]
+    {
+        framework = framework.command("calc", |c| c.exec(command::calc::calc));
+    }
     #[cfg(feature = "fuyu")]
     {
-        framework = framework.command("fuyu", |c| c.exec(command::fuyu::fuyu));
+        framework = framework.command("fuyu", |c| c.exec(command::fuyu::fuyu))
+            .command("markov", |c| c.exec(command::fuyu::markov));
     }
     #[cfg(feature = "help")]
     {
@@ -163,6 +223,10 @@ fn build_framework(framework: Framework) -> Framework {
                 .owners_only(true)
         });
     }
+    #[cfg(any(feature = "wolfram", feature = "xkcd"))]
+    {
+        framework = framework.command("quota", |c| c.exec(command::quota::quota));
+    }
     #[cfg(feature = "roll")]
     {
         framework = framework.command("roll", |c| c.exec(command::roll::roll));
@@ -173,11 +237,17 @@ fn build_framework(framework: Framework) -> Framework {
     }
     #[cfg(feature = "tag")]
     {
-        framework = framework.command("tag", |c| c.exec(command::tag::tag));
+        framework = framework.command("tag", |c| c.exec(command::tag::tag))
+            .command("autoresponder", |c| c.exec(command::tag::autoresponder));
     }
     #[cfg(feature = "wolfram")]
     {
-        framework = framework.command("wolfram", |c| c.exec(command::wolfram_alpha::wolfram));
+        // Without an app ID, the command only explains why it is unavailable.
+        framework = if command::wolfram_alpha::is_enabled() {
+            framework.command("wolfram", |c| c.exec(command::wolfram_alpha::wolfram))
+        } else {
+            framework.command("wolfram", |c| c.exec(command::wolfram_alpha::disabled))
+        };
     }
     #[cfg(feature = "xkcd")]
     {
@@ -187,6 +257,42 @@ fn build_framework(framework: Framework) -> Framework {
     framework
 }
 
+// Handles messages which are not processed by the command framework, passing
+// them on to any enabled plugins which react to regular messages.
+#[allow(unused_variables)]
+fn handle_message(context: Context, message: Message) {
+    #[cfg(feature = "fuyu")]
+    {
+        command::fuyu::handle_message(&message);
+    }
+    #[cfg(feature = "tag")]
+    {
+        command::tag::handle_message(&context, &message);
+    }
+    #[cfg(feature = "wolfram")]
+    {
+        command::wolfram_alpha::handle_message(&context, &message);
+    }
+}
+
+// Handles reactions being added to and removed from messages, passing them on
+// to any enabled plugins which react to them.
+#[allow(unused_variables)]
+fn handle_reaction_add(context: Context, reaction: Reaction) {
+    #[cfg(feature = "wolfram")]
+    {
+        command::wolfram_alpha::handle_reaction_add(&context, &reaction);
+    }
+}
+
+#[allow(unused_variables)]
+fn handle_reaction_remove(context: Context, reaction: Reaction) {
+    #[cfg(feature = "wolfram")]
+    {
+        command::wolfram_alpha::handle_reaction_remove(&context, &reaction);
+    }
+}
+
 // Creates a `Client`.
 fn login() -> (LoginType, Client) {
     debug!("Attempting to login");
@@ -0,0 +1,127 @@
+// Copyright (c) 2016 Nikita Pekin and the smexybot contributors
+// See the README.md file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Provides a simple JSON file-backed store for persistent bot state.
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+
+/// A value which is persisted to a JSON file on disk.
+#[derive(Debug)]
+pub struct Store<T> {
+    name: String,
+    data: T,
+}
+
+impl<T> Store<T>
+    where T: Default + Deserialize + Serialize,
+{
+    /// Returns a new `Store`, loading its contents from the file `name` if it
+    /// exists.
+    pub fn new(name: &str) -> Self {
+        let mut store = Store {
+            name: name.to_owned(),
+            data: T::default(),
+        };
+
+        if let Err(err) = store.load() {
+            panic!("{}", err);
+        }
+
+        store
+    }
+
+    /// Returns a new `Store` like `new`, but starts from the default value
+    /// instead of panicking if the file cannot be read, e.g. because it is
+    /// corrupt. The file is replaced on the next `save`, so this should only be
+    /// used for stores whose contents can be recreated, such as caches.
+    pub fn load_or_default(name: &str) -> Self {
+        let mut store = Store {
+            name: name.to_owned(),
+            data: T::default(),
+        };
+
+        if let Err(err) = store.load() {
+            warn!("{}; starting with an empty store", err);
+        }
+
+        store
+    }
+
+    /// Returns a reference to the stored value.
+    pub fn get(&self) -> &T {
+        &self.data
+    }
+
+    /// Returns a mutable reference to the stored value.
+    ///
+    /// Changes are not persisted until `save` is called.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        let mut file = match File::open(&self.name) {
+            Ok(file) => file,
+            // If no file is present, assume this is a fresh store.
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(format!("Failed to open file {}: {}", self.name, err)),
+        };
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .map_err(|err| format!("Failed to read from file {}: {}", self.name, err))?;
+        self.data = serde_json::from_str(&contents)
+            .map_err(|err| format!("Failed to deserialize store {}: {}", self.name, err))?;
+        debug!("Loaded store from: {}", self.name);
+
+        Ok(())
+    }
+
+    /// Writes the stored value to disk.
+    pub fn save(&self) {
+        let temp = format!("{}.tmp", self.name);
+        let mut file = File::create(&temp).expect(&format!("Failed to create file: {}", temp));
+        file.write_all(serde_json::to_string(&self.data)
+                .expect("Failed to serialize store")
+                .as_bytes())
+            .expect(&format!("Failed to write to file: {}", temp));
+
+        // Atomically replace the old store.
+        fs::rename(temp, &self.name).expect("Failed to write new store");
+        trace!("Saved store to: {}", self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use super::Store;
+
+    #[test]
+    fn test_load_or_default_ignores_corrupt_file() {
+        let path = env::temp_dir().join("smexybot-store-corrupt.json");
+        File::create(&path).unwrap().write_all(b"{\"truncated\": ").unwrap();
+        let name = path.to_str().unwrap();
+
+        let mut store = Store::<HashMap<String, u64>>::load_or_default(name);
+        assert!(store.get().is_empty());
+
+        store.get_mut().insert("key".to_owned(), 1);
+        store.save();
+        let store = Store::<HashMap<String, u64>>::new(name);
+        assert_eq!(store.get().get("key"), Some(&1));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
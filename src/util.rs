@@ -7,9 +7,11 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use ::CONFIG;
 use chrono::{DateTime, Duration, UTC};
 use rand::{self, Rng};
 use serenity::Result as SerenityResult;
+use serenity::client::CACHE;
 use serenity::model::Message;
 use serenity::utils::Colour;
 use std::collections::HashMap;
@@ -35,6 +37,31 @@ pub fn check_msg(result: SerenityResult<Message>) {
     }
 }
 
+/// Checks whether the author of a message may administer the bot in the
+/// message's guild.
+///
+/// Bot owners are always considered administrators. Otherwise, the author must
+/// have the "Manage Server" permission in the guild the message was sent in.
+pub fn is_admin(message: &Message) -> bool {
+    if CONFIG.owners.contains(&message.author.id.0) {
+        return true;
+    }
+
+    let guild_id = match message.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return false,
+    };
+    let cache = match CACHE.read() {
+        Ok(cache) => cache,
+        Err(_) => return false,
+    };
+
+    cache.guilds
+        .get(&guild_id)
+        .map(|guild| guild.permissions_for(message.channel_id, message.author.id).manage_guild())
+        .unwrap_or(false)
+}
+
 /// Generates a random RGB colour.
 #[inline]
 pub fn random_colour() -> Colour {